@@ -0,0 +1,325 @@
+//! Bitboard representation of the board, where pattern search is done with masks and shifts.
+//!
+//! The board is viewed as a set of lines along each of the 4 axes (the first 4 of the 8 directions).
+//! For every line, there is a bitmask for each of the actual elements (EMPTY, BLACK, WHITE, WALL),
+//! with bit i set if the element is at the i'th point of the line.
+//! The masks of BLACK and WHITE are the per-color bitsets, viewed along each axis.
+
+use crate::consts::{ACT_ELEMS, BLACK, EMPTY, NUM_AXES, SIDE_LEN, WHITE};
+use crate::geometry::{increments, Point};
+use crate::pattern::{Threat, ThreatPri, PATTERNS_BY_PRI};
+use crate::pattern_search::{get_pattern, Match};
+use fnv::FnvHashMap;
+use lazy_static::lazy_static;
+use ndarray::prelude::*;
+
+/// Maximum number of lines along an axis (for the diagonals).
+pub const MAX_LINES: usize = 2 * SIDE_LEN - 1;
+
+/// Number of actual elements, i.e., the number of masks per line.
+const NUM_ELEMS: usize = ACT_ELEMS.len();
+
+// Lines must fit in the masks.
+const _: () = assert!(SIDE_LEN <= 32);
+
+/// Lines along each axis.
+#[derive(Clone, Debug)]
+pub struct LineGeometry {
+    /// First point of each line, walking along the axis.
+    pub starts: [[Point; MAX_LINES]; NUM_AXES],
+    /// Number of points on each line.
+    pub lens: [[usize; MAX_LINES]; NUM_AXES],
+    /// Line and position on the line, of each point of the board.
+    pub cells: [[[(usize, usize); SIDE_LEN]; SIDE_LEN]; NUM_AXES],
+}
+
+lazy_static! {
+    pub static ref LINES: LineGeometry = {
+        let mut lines = LineGeometry {
+            starts: [[(0, 0); MAX_LINES]; NUM_AXES],
+            lens: [[0; MAX_LINES]; NUM_AXES],
+            cells: [[[(0, 0); SIDE_LEN]; SIDE_LEN]; NUM_AXES],
+        };
+
+        let in_bounds = |p: Point| p.0 >= 0 && p.1 >= 0 && p.0 < SIDE_LEN as isize && p.1 < SIDE_LEN as isize;
+
+        for a in 0..NUM_AXES {
+            let (row_inc, col_inc) = increments(a);
+            let mut line_idxs: FnvHashMap<Point, usize> = FnvHashMap::default();
+
+            for x in 0..SIDE_LEN {
+                for y in 0..SIDE_LEN {
+                    let mut start = (x as isize, y as isize);
+                    let mut pos: usize = 0;
+                    while in_bounds((start.0 - row_inc, start.1 - col_inc)) {
+                        start = (start.0 - row_inc, start.1 - col_inc);
+                        pos += 1;
+                    }
+
+                    let num_lines = line_idxs.len();
+                    let line = *line_idxs.entry(start).or_insert(num_lines);
+                    lines.starts[a][line] = start;
+                    lines.lens[a][line] = lines.lens[a][line].max(pos + 1);
+                    lines.cells[a][x][y] = (line, pos);
+                }
+            }
+
+            assert!(line_idxs.len() <= MAX_LINES);
+        }
+
+        lines
+    };
+}
+
+/// Index of the mask of the given actual element.
+#[inline(always)]
+fn elem_idx(elem: u8) -> usize {
+    elem.trailing_zeros() as usize
+}
+
+/// The point at the given position on the given line.
+#[inline(always)]
+fn line_point(a: usize, line: usize, pos: usize) -> Point {
+    let (row_inc, col_inc) = increments(a);
+    let start = LINES.starts[a][line];
+    (start.0 + row_inc * pos as isize, start.1 + col_inc * pos as isize)
+}
+
+/// Starting positions (as a bitmask) at which the given color specific pattern matches the line,
+/// reading the pattern backwards if reversed.
+#[inline(always)]
+fn line_matches(masks: &[u32; NUM_ELEMS], len: usize, pattern: &[u8], reversed: bool, mut starts: u32) -> u32 {
+    let length = pattern.len();
+    if length > len {
+        return 0;
+    }
+
+    // Patterns must fit on the line.
+    starts &= (1 << (len - length + 1)) - 1;
+
+    for k in 0..length {
+        if starts == 0 {
+            break;
+        }
+
+        let elem = if reversed { pattern[length - 1 - k] } else { pattern[k] };
+
+        let mut allowed: u32 = 0;
+        for (e, mask) in masks.iter().enumerate() {
+            if elem & (1 << e) != 0 {
+                allowed |= mask;
+            }
+        }
+
+        starts &= allowed >> k;
+    }
+
+    starts
+}
+
+/// Board as bitmasks along the lines of each axis.
+#[derive(Clone, Debug)]
+pub struct BitBoard {
+    masks: [[[u32; NUM_ELEMS]; MAX_LINES]; NUM_AXES],
+}
+
+impl BitBoard {
+    pub fn from_array(board: &Array2<u8>) -> Self {
+        assert_eq!(board.shape(), [SIDE_LEN, SIDE_LEN]);
+
+        let mut bitboard = Self {
+            masks: [[[0; NUM_ELEMS]; MAX_LINES]; NUM_AXES],
+        };
+
+        for ((x, y), elem) in board.indexed_iter() {
+            bitboard.put((x as isize, y as isize), *elem);
+        }
+
+        bitboard
+    }
+
+    pub fn to_array(&self) -> Array2<u8> {
+        Array::from_shape_fn((SIDE_LEN, SIDE_LEN), |(x, y)| self.get((x as isize, y as isize)))
+    }
+
+    /// Element at the given point.
+    pub fn get(&self, point: Point) -> u8 {
+        let (line, pos) = LINES.cells[0][point.0 as usize][point.1 as usize];
+        let masks = &self.masks[0][line];
+        ACT_ELEMS.iter().copied().find(|&e| masks[elem_idx(e)] & (1 << pos) != 0).unwrap()
+    }
+
+    /// Put the given element at point, which must be unoccupied (by any element) on all axes.
+    fn put(&mut self, point: Point, elem: u8) {
+        for a in 0..NUM_AXES {
+            let (line, pos) = LINES.cells[a][point.0 as usize][point.1 as usize];
+            self.masks[a][line][elem_idx(elem)] |= 1 << pos;
+        }
+    }
+
+    /// Remove the given element from point.
+    fn remove(&mut self, point: Point, elem: u8) {
+        for a in 0..NUM_AXES {
+            let (line, pos) = LINES.cells[a][point.0 as usize][point.1 as usize];
+            self.masks[a][line][elem_idx(elem)] &= !(1 << pos);
+        }
+    }
+
+    /// Sets the given square on the board to the given color.
+    pub fn set_sq(&mut self, color: u8, point: Point) {
+        assert!(color == BLACK || color == WHITE);
+        assert_eq!(self.get(point), EMPTY);
+        self.remove(point, EMPTY);
+        self.put(point, color);
+    }
+
+    /// Clears the given square on the board of the given color.
+    pub fn clear_sq(&mut self, color: u8, point: Point) {
+        assert!(color == BLACK || color == WHITE);
+        assert_eq!(self.get(point), color);
+        self.remove(point, color);
+        self.put(point, EMPTY);
+    }
+
+    /// Matches of the pattern on the given line, in both orientations, with the allowed starting positions (in the forward orientation).
+    ///
+    /// A segment that matches in both orientations is only recorded in the forward orientation, so there are no duplicates.
+    #[inline(always)]
+    fn search_line(&self, pattern: &[u8], a: usize, line: usize, starts: (u32, u32), matches: &mut Vec<Match>) {
+        let len = LINES.lens[a][line];
+        let length = pattern.len();
+        let masks = &self.masks[a][line];
+
+        // Forward, i.e., along direction a.
+        let fwd_all = line_matches(masks, len, pattern, false, starts.0);
+        let mut fwd = fwd_all;
+        while fwd != 0 {
+            let s = fwd.trailing_zeros() as usize;
+            fwd &= fwd - 1;
+            matches.push((line_point(a, line, s), line_point(a, line, s + length - 1)));
+        }
+
+        // Backward, i.e., along direction a + NUM_AXES.
+        let mut bwd = line_matches(masks, len, pattern, true, starts.1) & !fwd_all;
+        while bwd != 0 {
+            let s = bwd.trailing_zeros() as usize;
+            bwd &= bwd - 1;
+            matches.push((line_point(a, line, s + length - 1), line_point(a, line, s)));
+        }
+    }
+
+    /// Search for a (color specific) 1d pattern on the board.
+    fn search_board_into(&self, pattern: &[u8], matches: &mut Vec<Match>) {
+        for a in 0..NUM_AXES {
+            for line in 0..MAX_LINES {
+                if LINES.lens[a][line] > 0 {
+                    self.search_line(pattern, a, line, (u32::MAX, u32::MAX), matches);
+                }
+            }
+        }
+    }
+
+    /// Search for a (color specific) 1d pattern on the board including the given point.
+    fn search_point_into(&self, pattern: &[u8], point: Point, matches: &mut Vec<Match>) {
+        let length = pattern.len();
+
+        for a in 0..NUM_AXES {
+            let (line, pos) = LINES.cells[a][point.0 as usize][point.1 as usize];
+
+            // Starting positions from pos - (length - 1) to pos.
+            let lo = pos.saturating_sub(length - 1);
+            let starts = ((1u64 << (pos + 1)) - (1u64 << lo)) as u32;
+            self.search_line(pattern, a, line, (starts, starts), matches);
+        }
+    }
+
+    /// Search for a (color specific) 1d pattern on the board including the given point as an own_sq.
+    fn search_point_own_into(&self, pattern: &[u8], color: u8, point: Point, own_sqs: &[isize], matches: &mut Vec<Match>) {
+        if self.get(point) == color {
+            let length = pattern.len() as isize;
+
+            for a in 0..NUM_AXES {
+                let (line, pos) = LINES.cells[a][point.0 as usize][point.1 as usize];
+                let pos = pos as isize;
+
+                // The point is at own_sq from the start of the pattern, in either orientation.
+                let mut starts: (u32, u32) = (0, 0);
+                for own_sq in own_sqs.iter() {
+                    let fwd = pos - own_sq;
+                    let bwd = pos - (length - 1 - own_sq);
+                    if fwd >= 0 {
+                        starts.0 |= 1 << fwd;
+                    }
+                    if bwd >= 0 {
+                        starts.1 |= 1 << bwd;
+                    }
+                }
+
+                self.search_line(pattern, a, line, starts, matches);
+            }
+        }
+    }
+
+    /// Search for a 1d pattern on the board. Same as pattern_search::search_board.
+    pub fn search_board(&self, gen_pattern: &[u8], color: u8) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        self.search_board_into(&get_pattern(gen_pattern, color), &mut matches);
+        matches
+    }
+
+    /// Search for a 1d pattern on the board including the given point. Same as pattern_search::search_point.
+    pub fn search_point(&self, gen_pattern: &[u8], color: u8, point: Point) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        self.search_point_into(&get_pattern(gen_pattern, color), point, &mut matches);
+        matches
+    }
+
+    /// Search for a 1d pattern on the board including the given point as an own_sq. Same as pattern_search::search_point_own.
+    pub fn search_point_own(&self, gen_pattern: &[u8], color: u8, point: Point, own_sqs: &[isize]) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        self.search_point_own_into(&get_pattern(gen_pattern, color), color, point, own_sqs, &mut matches);
+        matches
+    }
+
+    /// Get all pattern matches on the board. Same as pattern::search_all_board.
+    pub fn search_all_board(&self, color: u8, pri: ThreatPri) -> Vec<Threat> {
+        let mut threats: Vec<Threat> = Vec::new();
+
+        let mut matches: Vec<Match> = Vec::new();
+        for p in PATTERNS_BY_PRI[&pri] {
+            matches.clear();
+            self.search_board_into(p.for_color(color), &mut matches);
+            threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+        }
+
+        threats
+    }
+
+    /// Get all pattern matches including the given point. Same as pattern::search_all_point.
+    pub fn search_all_point(&self, color: u8, point: Point, pri: ThreatPri) -> Vec<Threat> {
+        let mut threats: Vec<Threat> = Vec::new();
+
+        let mut matches: Vec<Match> = Vec::new();
+        for p in PATTERNS_BY_PRI[&pri] {
+            matches.clear();
+            self.search_point_into(p.for_color(color), point, &mut matches);
+            threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+        }
+
+        threats
+    }
+
+    /// Get all pattern matches including the given point as an own_sq. Same as pattern::search_all_point_own.
+    pub fn search_all_point_own(&self, color: u8, point: Point, pri: ThreatPri) -> Vec<Threat> {
+        let mut threats: Vec<Threat> = Vec::new();
+
+        let mut matches: Vec<Match> = Vec::new();
+        for p in PATTERNS_BY_PRI[&pri] {
+            matches.clear();
+            self.search_point_own_into(p.for_color(color), color, point, &p.own_sqs, &mut matches);
+            threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+        }
+
+        threats
+    }
+}
@@ -131,11 +131,44 @@ lazy_static! {
 
         m
     };
+
+    /// Map from generic element to its character in the text representation of patterns.
+    pub static ref GEN_ELEMS_TO_CHRS: FnvHashMap<u8, char> = {
+        let mut m = FnvHashMap::default();
+        m.insert(EMPTY, '_');
+        m.insert(WALL, '#');
+        m.insert(OWN, 'x');
+        m.insert(ENEMY, 'o');
+        m.insert(STONE, '@');
+        m.insert(ANY, '?');
+        m.insert(NOT_EMPTY, '+');
+        m.insert(NOT_WALL, '.');
+        m.insert(NOT_STONE, '-');
+        m.insert(NOT_OWN, '!');
+        m.insert(WALL_ENEMY, '|');
+
+        // Make sure all GEN_ELEMS are represented and corresponding chars are unique.
+        assert_eq!(m.keys().cloned().collect::<FnvHashSet<u8>>(),
+                   GEN_ELEMS.iter().cloned().collect::<FnvHashSet<u8>>());
+        assert_eq!(m.values().cloned().collect::<FnvHashSet<char>>().len(), GEN_ELEMS.len());
+        assert!(!m.values().any(|&x| x == CRITICAL_SQ_CHR));
+
+        m
+    };
 }
 
+/// Character for a critical square (which is EMPTY) in the text representation of patterns.
+pub const CRITICAL_SQ_CHR: char = '*';
+
+/// Environment variable with the path of a pattern set file, to be used instead of the built-in patterns.
+pub const PATTERNS_FILE_ENV: &str = "GOMOKU_PATTERNS";
+
 /// 4 cardinal directions + 4 ordinal directions.
 pub const NUM_DIRECTIONS: usize = 8;
 
+/// Number of axes. Directions d and d + NUM_AXES lie on the same axis (in opposite orientations).
+pub const NUM_AXES: usize = NUM_DIRECTIONS / 2;
+
 /// WIN_LENGTH is the length of a winning sequence.
 /// Some things implicitly assume a win length of 5, for example, threat pattern definitions.
 /// Don't change WIN_LENGTH without making all other relevant changes everywhere else in the project.
@@ -145,6 +178,9 @@ pub const WIN_LENGTH: usize = 5;
 /// Effectively, the maximum distance away from winning.
 pub const MAX_DEFCON: usize = WIN_LENGTH;
 
+/// Length of the longest threat pattern (see pattern_table::WINDOW_LEN).
+pub const MAX_PATTERN_LEN: usize = 7;
+
 /// Max defcon for an immediate threat.
 pub const MDFIT: usize = 2;
 
@@ -153,3 +189,10 @@ pub const RADIX: u32 = 36;
 
 /// The timestep for animation of a variation.
 pub const ANIMATION_TIMESTEP_SECS: u64 = 2;
+
+/// Threat Space Search is parallelized over the subtrees at depths up to (and including) this depth, by default.
+/// Deeper splitting is supported (see TSSLimits::par_max_depth), but bench_tss_parallel hasn't shown it to be faster yet.
+pub const TSS_PAR_MAX_DEPTH: usize = 1;
+
+/// Threat Space Search is parallelized over the subtrees of a node only if it has at least this many of them.
+pub const TSS_PAR_MIN_CHILDREN: usize = 4;
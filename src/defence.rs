@@ -0,0 +1,125 @@
+//! Defensive analysis: moves that refute the opponent's potentially winning threat sequences.
+
+use crate::board::{clear_sq, set_sq};
+use crate::consts::{EMPTY, MAX_DEFCON, STONE};
+use crate::geometry::{chebyshev_distance, point_on_line, Point};
+use crate::pattern::{search_all_board, ThreatPri, P_WIN};
+use crate::pattern_search::search_point;
+use crate::threat_space_search::{potential_win_variations, tss_board_limited, SearchTree, TSSLimits, TSSMode};
+use fnv::FnvHashSet;
+use ndarray::prelude::*;
+
+/// Result of a defensive analysis.
+///
+/// If the opponent has no potentially winning threat sequence, then threatened is false,
+/// and there is nothing to defend against.
+/// candidates are the squares common to all of the opponent's potentially winning variations,
+/// and defences are the candidates that were confirmed by re-running TSS after playing them.
+#[derive(Clone, Debug)]
+pub struct DefenceResult {
+    pub threatened: bool,
+    pub candidates: Vec<Point>,
+    pub defences: Vec<Point>,
+}
+
+/// Squares involved in the given variation: the next_sqs, and the critical_sqs.
+pub fn variation_sqs(variation: &[(Point, FnvHashSet<Point>)]) -> FnvHashSet<Point> {
+    let mut sqs: FnvHashSet<Point> = FnvHashSet::default();
+
+    for item in variation.iter() {
+        sqs.insert(item.0);
+        sqs.extend(item.1.iter());
+    }
+
+    sqs
+}
+
+/// Empty squares on the segments of the given color's most pressing threats.
+pub fn pressing_threat_sqs(board: &Array2<u8>, color: u8) -> FnvHashSet<Point> {
+    let threats = search_all_board(board, color, ThreatPri::Immediate);
+    let min_defcon = threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
+
+    let mut sqs: FnvHashSet<Point> = FnvHashSet::default();
+
+    for t in threats.iter().filter(|x| x.defcon == min_defcon) {
+        for i in 0..=chebyshev_distance(t.m.0, t.m.1) {
+            let p = point_on_line(t.m.0, t.m.1, i);
+            if board[(p.0 as usize, p.1 as usize)] == EMPTY {
+                sqs.insert(p);
+            }
+        }
+    }
+
+    sqs
+}
+
+/// Candidate defences against the opponent's TSS (tree): squares common to all of the potentially winning variations.
+///
+/// If the opponent is already potentially winning at the root, i.e., before making any move,
+/// the candidates are the empty squares on the segments of the opponent's most pressing threats.
+pub fn defence_candidates(board: &Array2<u8>, opp: u8, tree: &SearchTree) -> FnvHashSet<Point> {
+    let mut candidates: Option<FnvHashSet<Point>> = None;
+
+    for v in potential_win_variations(tree).iter() {
+        let sqs = if v.is_empty() {
+            pressing_threat_sqs(board, opp)
+        } else {
+            variation_sqs(v)
+        };
+
+        candidates = Some(match candidates {
+            Some(c) => c.intersection(&sqs).copied().collect::<FnvHashSet<Point>>(),
+            None => sqs,
+        });
+    }
+
+    candidates.unwrap_or_default()
+}
+
+/// Moves for color that stop every potentially winning threat sequence of the opponent.
+///
+/// Only candidates that lie on all of the opponent's potentially winning variations are considered,
+/// so defences that work by counter-attacking elsewhere are not found.
+/// A candidate is confirmed if the opponent's TSS (in the same mode and within the same limits) finishes without a potential win.
+pub fn defence_sqs(board: &mut Array2<u8>, color: u8, mode: TSSMode, limits: &TSSLimits) -> DefenceResult {
+    let opp = color ^ STONE;
+    let tree = tss_board_limited(board, opp, mode, limits).tree;
+
+    if !tree.root().potential_win {
+        return DefenceResult {
+            threatened: false,
+            candidates: Vec::new(),
+            defences: Vec::new(),
+        };
+    }
+
+    let mut candidates = defence_candidates(board, opp, &tree)
+        .into_iter()
+        .filter(|x| board[(x.0 as usize, x.1 as usize)] == EMPTY)
+        .collect::<Vec<Point>>();
+    candidates.sort_unstable();
+
+    let mut defences: Vec<Point> = Vec::new();
+
+    for sq in candidates.iter() {
+        set_sq(board, color, *sq);
+
+        let won = !search_point(board, &P_WIN.pattern, color, *sq).is_empty();
+        let confirmed = won || {
+            let result = tss_board_limited(board, opp, mode, limits);
+            result.finished && !result.tree.root().potential_win
+        };
+
+        clear_sq(board, color, *sq);
+
+        if confirmed {
+            defences.push(*sq);
+        }
+    }
+
+    DefenceResult {
+        threatened: true,
+        candidates,
+        defences,
+    }
+}
@@ -0,0 +1,398 @@
+//! Game playing search: negamax with alpha-beta pruning, iterative deepening, and aspiration windows.
+//!
+//! Leaves are scored by the static evaluation, and Threat Space Search is used as a forced win probe at the nodes with
+//! a remaining depth of at least TSS_PROBE_MIN_DEPTH:
+//! if the side to move has a potential win, the node is scored just below TSS_WIN_SCORE (see tss_probe), and isn't searched any further.
+//! A potential win isn't proven (the opponent may defend with fours of its own), so it's a heuristic score, not a mate score.
+//! At the root, the probe only puts the first move of the variation first, and the moves are searched as usual.
+//! Scores follow the scale in evaluation.rs, with mate scores counted in plies from the root.
+//!
+//! Only the EMPTY squares near the stones are searched, the most promising ones first (see candidate_moves),
+//! and if the opponent has a four, only the squares that block it.
+
+use crate::board::{clear_sq, point_to_algebraic, set_sq};
+use crate::consts::{EMPTY, SIDE_LEN, SIDE_LEN_ACT, STONE};
+use crate::evaluation::{evaluate_board, is_mate_score, mate_plies, Score, LOSS_SCORE, MAX_EVAL, MAX_MATE_PLY, WIN_SCORE};
+use crate::geometry::Point;
+use crate::pattern::{search_all_board, search_all_board_get_next_sqs, ThreatPri};
+use crate::threat_space_search::{potential_win_variations, tss_board_limited, TSSLimits, TSSMode};
+use fnv::FnvHashSet;
+use ndarray::prelude::*;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Deepest iteration, if the limits don't say otherwise.
+pub const MAX_ENGINE_DEPTH: usize = 32;
+
+/// Max number of moves searched at a node.
+pub const MAX_CANDIDATES: usize = 20;
+
+/// Moves are searched on the EMPTY squares within this (Chebyshev) distance of a stone.
+pub const CANDIDATE_DISTANCE: isize = 2;
+
+/// Node limit of the Threat Space Search probe.
+pub const TSS_PROBE_MAX_NODES: usize = 1000;
+
+/// The Threat Space Search probe is only used at nodes with (at least) this remaining depth.
+/// The nodes just above the leaves are most of the interior nodes: probing them too costs about 30 times as many TSS nodes.
+pub const TSS_PROBE_MIN_DEPTH: usize = 2;
+
+/// Score of a potential win found by the Threat Space Search probe, less the number of plies it takes (see tss_probe).
+pub const TSS_WIN_SCORE: Score = MAX_EVAL - MAX_MATE_PLY;
+
+/// Half width of the aspiration window around the score of the previous iteration.
+pub const ASPIRATION_WINDOW: Score = 50;
+
+/// Limits on the engine search. The search stops at whichever comes first.
+///
+/// max_depth None means MAX_ENGINE_DEPTH. The result of the last completed iteration is returned.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EngineLimits {
+    pub max_depth: Option<usize>,
+    pub max_nodes: Option<usize>,
+    pub deadline: Option<Instant>,
+}
+
+impl EngineLimits {
+    pub fn new(max_depth: Option<usize>, max_nodes: Option<usize>, deadline: Option<Instant>) -> Self {
+        Self {
+            max_depth,
+            max_nodes,
+            deadline,
+        }
+    }
+
+    /// True if the search has to stop after visiting num_nodes nodes.
+    pub fn reached(&self, num_nodes: usize) -> bool {
+        self.max_nodes.is_some_and(|x| num_nodes >= x) || self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+}
+
+/// Result of an engine search, from the POV of the side to move.
+///
+/// pv is the principal variation, starting with best_move.
+/// depth is the depth of the last completed iteration (0 if none was completed).
+#[derive(Clone, Debug)]
+pub struct EngineResult {
+    pub best_move: Option<Point>,
+    pub score: Score,
+    pub pv: Vec<Point>,
+    pub depth: usize,
+    pub num_nodes: usize,
+    pub num_tss_nodes: usize,
+}
+
+impl fmt::Display for EngineResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let score = match mate_plies(self.score) {
+            Some(x) if x >= 0 => format!("win in {} plies", x),
+            Some(x) => format!("loss in {} plies", -x),
+            None => self.score.to_string(),
+        };
+        let pv = self.pv.iter().map(|x| point_to_algebraic(*x)).collect::<Vec<String>>();
+
+        writeln!(f, "depth: {}", self.depth)?;
+        writeln!(f, "score: {}", score)?;
+        writeln!(f, "pv: {}", pv.join(" "))?;
+        writeln!(f, "num_nodes: {}", self.num_nodes)?;
+        writeln!(f, "num_tss_nodes: {}", self.num_tss_nodes)
+    }
+}
+
+/// Mate score relative to a node at the given ply, converted to be relative to the root. Other scores are unchanged.
+#[inline(always)]
+fn score_from_root(score: Score, ply: usize) -> Score {
+    if !is_mate_score(score) {
+        score
+    } else if score > 0 {
+        score - ply as Score
+    } else {
+        score + ply as Score
+    }
+}
+
+/// EMPTY squares to search at a node, the most promising ones first, at most MAX_CANDIDATES of them.
+///
+/// If the opponent has fours, only the squares that block all of them are searched (if there are any).
+/// Otherwise, moves that make a five or a four come first, then the ones that make any other Immediate threat,
+/// then the ones that stop the opponent from making one,
+/// then the rest of the EMPTY squares within CANDIDATE_DISTANCE of a stone, by the number of stones next to them.
+/// On an empty board, the only move is the center.
+pub fn candidate_moves(board: &Array2<u8>, color: u8) -> Vec<Point> {
+    let blocks = search_all_board(board, color ^ STONE, ThreatPri::Four)
+        .into_iter()
+        .map(|x| x.critical_sqs)
+        .reduce(|a, b| a.intersection(&b).copied().collect::<FnvHashSet<Point>>());
+    if let Some(x) = blocks.filter(|x| !x.is_empty()) {
+        let mut moves = x.into_iter().collect::<Vec<Point>>();
+        moves.sort_unstable();
+        return moves;
+    }
+
+    let side = SIDE_LEN as isize;
+    let stones = board.indexed_iter().filter(|x| x.1 & STONE != 0).count();
+    if stones == 0 {
+        let center = (SIDE_LEN_ACT / 2 + 1) as isize;
+        return vec![(center, center)];
+    }
+
+    let own_fours = search_all_board_get_next_sqs(board, color, ThreatPri::Four);
+    let own_nsqs = search_all_board_get_next_sqs(board, color, ThreatPri::Immediate);
+    let opp_nsqs = search_all_board_get_next_sqs(board, color ^ STONE, ThreatPri::Immediate);
+
+    // Number of stones within the given distance of the point.
+    let nearby = |point: Point, distance: isize| {
+        let mut n = 0;
+        for i in (point.0 - distance).max(0)..=(point.0 + distance).min(side - 1) {
+            for j in (point.1 - distance).max(0)..=(point.1 + distance).min(side - 1) {
+                if board[(i as usize, j as usize)] & STONE != 0 {
+                    n += 1;
+                }
+            }
+        }
+        n
+    };
+
+    let mut moves = board
+        .indexed_iter()
+        .filter(|x| *x.1 == EMPTY)
+        .map(|((x, y), _)| (x as isize, y as isize))
+        .filter(|x| nearby(*x, CANDIDATE_DISTANCE) > 0)
+        .map(|x| {
+            let priority = if own_fours.contains(&x) {
+                3
+            } else if own_nsqs.contains(&x) {
+                2
+            } else if opp_nsqs.contains(&x) {
+                1
+            } else {
+                0
+            };
+            (priority, nearby(x, 1), x)
+        })
+        .collect::<Vec<(usize, usize, Point)>>();
+
+    // Highest priority first. Ties are broken by the point, so that the order doesn't depend on the hashing.
+    moves.sort_unstable_by(|a, b| (b.0, b.1).cmp(&(a.0, a.1)).then(a.2.cmp(&b.2)));
+    moves.truncate(MAX_CANDIDATES);
+    moves.into_iter().map(|x| x.2).collect()
+}
+
+/// State of a single engine search.
+struct Engine<'a> {
+    limits: &'a EngineLimits,
+    num_nodes: usize,
+    num_tss_nodes: usize,
+    stopped: bool,
+    /// Principal variation of the previous iteration, searched first.
+    prev_pv: Vec<Point>,
+}
+
+impl<'a> Engine<'a> {
+    fn new(limits: &'a EngineLimits) -> Self {
+        Self {
+            limits,
+            num_nodes: 0,
+            num_tss_nodes: 0,
+            stopped: false,
+            prev_pv: Vec::new(),
+        }
+    }
+
+    /// Threat Space Search (VCT) for color, as a forced win probe.
+    ///
+    /// A potential win takes 2 * n + 3 plies, where n is the length of the shortest variation found,
+    /// if the opponent's only defences are the critical squares:
+    /// 2 * n - 1 plies to play the variation, and at most 4 more to turn the resulting double threat into a five.
+    /// Returns the number of plies, and the first move of the variation (None if color is already threatening to win).
+    fn tss_probe(&mut self, board: &mut Array2<u8>, color: u8) -> Option<(Score, Option<Point>)> {
+        let limits = TSSLimits::new(None, Some(TSS_PROBE_MAX_NODES), self.limits.deadline, Arc::new(AtomicBool::new(false)));
+        let result = tss_board_limited(board, color, TSSMode::VCT, &limits);
+        self.num_tss_nodes += result.num_nodes;
+
+        if !result.tree.root().potential_win {
+            return None;
+        }
+
+        let variations = potential_win_variations(&result.tree);
+        let variation = &variations[0];
+        Some((2 * variation.len() as Score + 3, variation.first().map(|x| x.0)))
+    }
+
+    /// Negamax search with alpha-beta pruning (fail-soft). ply is the distance from the root.
+    ///
+    /// on_pv is true if all the moves leading to the node are on the principal variation of the previous iteration.
+    /// The principal variation of the node is stored in pv. The result is meaningless if the search was stopped.
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        board: &mut Array2<u8>,
+        color: u8,
+        depth: usize,
+        ply: usize,
+        mut alpha: Score,
+        beta: Score,
+        on_pv: bool,
+        pv: &mut Vec<Point>,
+    ) -> Score {
+        pv.clear();
+        self.num_nodes += 1;
+
+        if self.stopped || self.limits.reached(self.num_nodes) {
+            self.stopped = true;
+            return 0;
+        }
+
+        // The static evaluation only returns the mate scores that it can prove.
+        // At the root, the moves are still searched (unless the game is over), to find the move to play.
+        let score = evaluate_board(board, color);
+        if is_mate_score(score) && (ply > 0 || mate_plies(score) == Some(0)) {
+            return score_from_root(score, ply);
+        }
+
+        if depth == 0 {
+            return score;
+        }
+
+        // At the root, the first move of the variation is searched first instead.
+        let probe = if depth >= TSS_PROBE_MIN_DEPTH {
+            self.tss_probe(board, color)
+        } else {
+            None
+        };
+        let probe_move = probe.and_then(|x| x.1);
+        if let Some((plies, sq)) = probe.filter(|_| ply > 0) {
+            pv.extend(sq);
+            return score.max(TSS_WIN_SCORE - plies);
+        }
+
+        let mut moves = candidate_moves(board, color);
+        if moves.is_empty() {
+            // The board is full: a draw.
+            return 0;
+        }
+
+        // Move ordering: the move on the previous principal variation first, or else the first move of the probe's variation.
+        let pv_move = if on_pv { self.prev_pv.get(ply).copied() } else { None };
+        if let Some(x) = pv_move.or(probe_move) {
+            if let Some(i) = moves.iter().position(|y| *y == x) {
+                moves[..=i].rotate_right(1);
+            }
+        }
+
+        let mut best = LOSS_SCORE - 1;
+        let mut child_pv: Vec<Point> = Vec::new();
+
+        for sq in moves {
+            set_sq(board, color, sq);
+            let child_on_pv = pv_move == Some(sq);
+            let score = -self.negamax(board, color ^ STONE, depth - 1, ply + 1, -beta, -alpha, child_on_pv, &mut child_pv);
+            clear_sq(board, color, sq);
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(sq);
+                    pv.extend_from_slice(&child_pv);
+                }
+
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Search at the root to the given depth, within an aspiration window around the score of the previous iteration.
+    ///
+    /// On a fail low (high), the window is widened on that side, and the search is repeated.
+    /// There's no window for the first iteration, or after a mate score.
+    fn search_root(&mut self, board: &mut Array2<u8>, color: u8, depth: usize, prev_score: Option<Score>, pv: &mut Vec<Point>) -> Score {
+        let (mut alpha, mut beta, mut delta) = match prev_score {
+            Some(x) if !is_mate_score(x) => (x - ASPIRATION_WINDOW, x + ASPIRATION_WINDOW, ASPIRATION_WINDOW),
+            _ => (LOSS_SCORE - 1, WIN_SCORE + 1, 0),
+        };
+
+        loop {
+            let score = self.negamax(board, color, depth, 0, alpha, beta, true, pv);
+
+            if self.stopped {
+                return score;
+            }
+
+            if score <= alpha && alpha > LOSS_SCORE - 1 {
+                delta *= 2;
+                alpha = if is_mate_score(score) {
+                    LOSS_SCORE - 1
+                } else {
+                    (score - delta).max(LOSS_SCORE - 1)
+                };
+            } else if score >= beta && beta < WIN_SCORE + 1 {
+                delta *= 2;
+                beta = if is_mate_score(score) {
+                    WIN_SCORE + 1
+                } else {
+                    (score + delta).min(WIN_SCORE + 1)
+                };
+            } else {
+                return score;
+            }
+        }
+    }
+}
+
+/// Search for the best move of color, using iterative deepening within the given limits.
+///
+/// Iterations go one ply deeper at a time, until a mate score is found or a limit is reached.
+/// If not even the first iteration is completed, the best move is the first of the candidate moves.
+pub fn engine_search(board: &Array2<u8>, color: u8, limits: &EngineLimits) -> EngineResult {
+    let mut board = board.to_owned();
+    let mut engine = Engine::new(limits);
+    let max_depth = limits.max_depth.unwrap_or(MAX_ENGINE_DEPTH);
+
+    let mut result = EngineResult {
+        best_move: candidate_moves(&board, color).first().copied(),
+        score: evaluate_board(&board, color),
+        pv: Vec::new(),
+        depth: 0,
+        num_nodes: 0,
+        num_tss_nodes: 0,
+    };
+
+    let mut prev_score: Option<Score> = None;
+    let mut pv: Vec<Point> = Vec::new();
+
+    for depth in 1..=max_depth {
+        let score = engine.search_root(&mut board, color, depth, prev_score, &mut pv);
+
+        if engine.stopped {
+            break;
+        }
+
+        result.best_move = pv.first().copied().or(result.best_move);
+        result.score = score;
+        result.pv = pv.to_owned();
+        result.depth = depth;
+        engine.prev_pv = pv.to_owned();
+        prev_score = Some(score);
+
+        if is_mate_score(score) {
+            break;
+        }
+    }
+
+    result.num_nodes = engine.num_nodes;
+    result.num_tss_nodes = engine.num_tss_nodes;
+    result
+}
@@ -0,0 +1,199 @@
+//! Static evaluation of a position, from the POV of the side to move.
+//!
+//! Evaluation scale (in Score units):
+//! - WIN_SCORE - n (LOSS_SCORE + n) is a win (loss) in n plies, i.e., mate-in-n scores are distinct and shorter wins score higher.
+//!   Any score with an absolute value above WIN_SCORE - MAX_MATE_PLY is a mate score. See win_in, loss_in and mate_plies.
+//! - Otherwise, the score is a heuristic in -MAX_EVAL..=MAX_EVAL (well below the mate scores),
+//!   where 0 is balanced and an open three is worth roughly OPEN_THREE_VALUE.
+//!
+//! The heuristic weighs each side's threats (all patterns, including the P_2_* ones) by defcon and immediacy,
+//! and adds bonuses for the tempo (the side to move) and for the shape (EMPTY squares where threats on different lines meet,
+//! where a single move may create a fork). The static evaluation only claims the mates it can prove without search:
+//! a four of the side to move (win in 1), and an unstoppable four of the opponent (loss in 2).
+
+use crate::consts::{BLACK, EMPTY, MDFIT, WHITE};
+use crate::geometry::{point_set_on_line, slope_intercept, Point};
+use crate::pattern::{search_all_board, Threat, ThreatPri, PATTERNS};
+use crate::state::{State, Status};
+use fnv::{FnvHashMap, FnvHashSet};
+use ndarray::prelude::*;
+
+/// Score of a position, from the POV of the side to move.
+pub type Score = i32;
+
+/// Score of a win on the board.
+pub const WIN_SCORE: Score = 1_000_000;
+
+/// Score of a loss on the board.
+pub const LOSS_SCORE: Score = -WIN_SCORE;
+
+/// Longest mate (in plies) that can be represented.
+pub const MAX_MATE_PLY: Score = 1000;
+
+/// Bound on the heuristic (non-mate) scores.
+pub const MAX_EVAL: Score = 100_000;
+
+// Heuristic scores must not be mistaken for mate scores.
+const _: () = assert!(MAX_EVAL < WIN_SCORE - MAX_MATE_PLY);
+
+/// Value of a four (which can be blocked by a single move).
+pub const FOUR_VALUE: Score = 1000;
+
+/// Value of an open (immediate) three, i.e., one that becomes a straight four unless it's blocked.
+pub const OPEN_THREE_VALUE: Score = 400;
+
+/// Value of a closed (non-immediate) three.
+pub const CLOSED_THREE_VALUE: Score = 100;
+
+/// Value of an open (immediate) two.
+pub const OPEN_TWO_VALUE: Score = 30;
+
+/// Value of a closed (non-immediate) threat of defcon >= 3.
+pub const CLOSED_TWO_VALUE: Score = 8;
+
+/// Bonus for being the side to move.
+pub const TEMPO_BONUS: Score = 20;
+
+/// Bonus for an open three of the side to move, when the opponent has no four: the opponent must respond to it,
+/// and a second one may be made with the next move.
+pub const INITIATIVE_BONUS: Score = 600;
+
+/// Bonus for each EMPTY square where threats (of defcon <= MDFIT + 1) on different lines meet.
+pub const SHAPE_BONUS: Score = 40;
+
+/// Score of a win in the given number of plies.
+#[inline(always)]
+pub fn win_in(plies: Score) -> Score {
+    assert!((0..MAX_MATE_PLY).contains(&plies));
+    WIN_SCORE - plies
+}
+
+/// Score of a loss in the given number of plies.
+#[inline(always)]
+pub fn loss_in(plies: Score) -> Score {
+    assert!((0..MAX_MATE_PLY).contains(&plies));
+    LOSS_SCORE + plies
+}
+
+/// Check if the score is a mate score.
+#[inline(always)]
+pub fn is_mate_score(score: Score) -> bool {
+    score.abs() > WIN_SCORE - MAX_MATE_PLY
+}
+
+/// Number of plies to the end of the game for a mate score: positive for a win and negative for a loss.
+pub fn mate_plies(score: Score) -> Option<Score> {
+    if !is_mate_score(score) {
+        None
+    } else if score > 0 {
+        Some(WIN_SCORE - score)
+    } else {
+        Some(-(score - LOSS_SCORE))
+    }
+}
+
+/// Heuristic value of a single threat, by the defcon and immediacy of its pattern.
+pub fn threat_value(threat: &Threat) -> Score {
+    let immediate = PATTERNS[threat.pidx].immediate;
+
+    match threat.defcon {
+        0 => WIN_SCORE,
+        1 => FOUR_VALUE,
+        2 if immediate => OPEN_THREE_VALUE,
+        2 => CLOSED_THREE_VALUE,
+        _ if immediate => OPEN_TWO_VALUE,
+        _ => CLOSED_TWO_VALUE,
+    }
+}
+
+/// Check if the threat is an open (immediate) three.
+#[inline(always)]
+fn is_open_three(threat: &Threat) -> bool {
+    threat.defcon == 2 && PATTERNS[threat.pidx].immediate
+}
+
+/// Check if the fours can't all be blocked by a single move,
+/// i.e., one of them has no critical squares (a straight four), or their critical squares don't overlap.
+fn unstoppable_fours(fours: &[&Threat]) -> bool {
+    if fours.iter().any(|x| x.critical_sqs.is_empty()) {
+        return true;
+    }
+
+    let mut common = match fours.first() {
+        Some(x) => x.critical_sqs.to_owned(),
+        None => return false,
+    };
+
+    for x in fours.iter().skip(1) {
+        common.retain(|sq| x.critical_sqs.contains(sq));
+    }
+
+    common.is_empty()
+}
+
+/// Number of EMPTY squares where threats (of defcon <= MDFIT + 1) on different lines meet.
+fn shape_count(board: &Array2<u8>, threats: &[Threat]) -> usize {
+    let mut lines: FnvHashMap<Point, FnvHashSet<(isize, isize, isize)>> = FnvHashMap::default();
+
+    for x in threats.iter().filter(|x| x.defcon > 0 && x.defcon <= MDFIT + 1) {
+        let line = slope_intercept(x.m.0, x.m.1);
+        for sq in point_set_on_line(x.m.0, x.m.1, &PATTERNS[x.pidx].empty_sqs) {
+            assert_eq!(board[(sq.0 as usize, sq.1 as usize)], EMPTY);
+            lines.entry(sq).or_default().insert(line);
+        }
+    }
+
+    lines.values().filter(|x| x.len() >= 2).count()
+}
+
+/// Heuristic value of the threats of one side, including the shape bonus.
+fn side_value(board: &Array2<u8>, threats: &[Threat]) -> Score {
+    let value: Score = threats.iter().map(threat_value).sum();
+    value + SHAPE_BONUS * shape_count(board, threats) as Score
+}
+
+/// Evaluate the board with color to move.
+pub fn evaluate_board(board: &Array2<u8>, color: u8) -> Score {
+    assert!(color == BLACK || color == WHITE);
+    let other = if color == BLACK { WHITE } else { BLACK };
+
+    let own = search_all_board(board, color, ThreatPri::All);
+    let enemy = search_all_board(board, other, ThreatPri::All);
+
+    // The game is already over.
+    if enemy.iter().any(|x| x.defcon == 0) {
+        return loss_in(0);
+    }
+    if own.iter().any(|x| x.defcon == 0) {
+        return win_in(0);
+    }
+
+    // A five can be made right away.
+    if own.iter().any(|x| x.defcon == 1) {
+        return win_in(1);
+    }
+
+    // The opponent makes a five with the next move, whatever is played.
+    let enemy_fours = enemy.iter().filter(|x| x.defcon == 1).collect::<Vec<&Threat>>();
+    if unstoppable_fours(&enemy_fours) {
+        return loss_in(2);
+    }
+
+    let mut score = side_value(board, &own) - side_value(board, &enemy) + TEMPO_BONUS;
+
+    // With a four to block, the side to move has no time to make use of its threes.
+    if enemy_fours.is_empty() && own.iter().any(is_open_three) {
+        score += INITIATIVE_BONUS;
+    }
+
+    score.clamp(-MAX_EVAL, MAX_EVAL)
+}
+
+/// Evaluate the state, from the POV of the side to move.
+pub fn evaluate(state: &State) -> Score {
+    match state.status {
+        Status::Ongoing => evaluate_board(&state.board, state.turn),
+        // The side to move is the one that lost.
+        Status::BlackWon | Status::WhiteWon => loss_in(0),
+    }
+}
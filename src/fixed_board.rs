@@ -0,0 +1,299 @@
+//! Fixed size (stack allocated) board, with squares addressed by a compact index.
+//!
+//! Square (x, y) has index x * SIDE_LEN + y, and moving along direction d adds DIR_OFFSETS[d] to the index.
+//! Since stepping by an offset wraps around at the edges, the number of steps that stay on the board
+//! (along each direction, from each square) is precomputed in STEPS.
+
+use crate::consts::{BLACK, EMPTY, NUM_AXES, NUM_DIRECTIONS, SIDE_LEN, WHITE};
+use crate::geometry::{increments, Point};
+use crate::pattern::{Threat, ThreatPri, PATTERNS_BY_PRI};
+use crate::pattern_search::{get_pattern, is_symmetric, Match};
+use lazy_static::lazy_static;
+use ndarray::prelude::*;
+
+/// Index of a square on the board.
+pub type Sq = u16;
+
+/// Number of squares on the board (including the walls).
+pub const NUM_SQS: usize = SIDE_LEN * SIDE_LEN;
+
+// Square indices must fit in Sq.
+const _: () = assert!(NUM_SQS <= Sq::MAX as usize);
+
+/// Change in square index when moving in direction d.
+pub const DIR_OFFSETS: [isize; NUM_DIRECTIONS] = {
+    let mut offsets = [0; NUM_DIRECTIONS];
+    let mut d = 0;
+    while d < NUM_DIRECTIONS {
+        let (row_inc, col_inc) = increments(d);
+        offsets[d] = row_inc * SIDE_LEN as isize + col_inc;
+        d += 1;
+    }
+    offsets
+};
+
+lazy_static! {
+    /// Number of steps that can be taken from each square along each direction, staying on the board.
+    pub static ref STEPS: [[u8; NUM_DIRECTIONS]; NUM_SQS] = {
+        let mut steps = [[0; NUM_DIRECTIONS]; NUM_SQS];
+        let side = SIDE_LEN as isize;
+
+        for (sq, x) in steps.iter_mut().enumerate() {
+            let point = to_point(sq as Sq);
+            for (d, n) in x.iter_mut().enumerate() {
+                let (row_inc, col_inc) = increments(d);
+                let (mut i, mut j) = point;
+                while (0..side).contains(&(i + row_inc)) && (0..side).contains(&(j + col_inc)) {
+                    i += row_inc;
+                    j += col_inc;
+                    *n += 1;
+                }
+            }
+        }
+
+        steps
+    };
+}
+
+/// Square index of the given point.
+#[inline(always)]
+pub fn to_sq(point: Point) -> Sq {
+    debug_assert!(point.0 >= 0 && point.1 >= 0 && point.0 < SIDE_LEN as isize && point.1 < SIDE_LEN as isize);
+    (point.0 as usize * SIDE_LEN + point.1 as usize) as Sq
+}
+
+/// Point of the given square index.
+#[inline(always)]
+pub fn to_point(sq: Sq) -> Point {
+    ((sq as usize / SIDE_LEN) as isize, (sq as usize % SIDE_LEN) as isize)
+}
+
+/// Square reached by taking the given number of steps along direction d.
+#[inline(always)]
+pub fn step(sq: Sq, d: usize, steps: isize) -> Sq {
+    (sq as isize + DIR_OFFSETS[d] * steps) as Sq
+}
+
+/// Board as a flat array of elements.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FixedBoard {
+    sqs: [u8; NUM_SQS],
+}
+
+impl FixedBoard {
+    pub fn from_array(board: &Array2<u8>) -> Self {
+        assert_eq!(board.shape(), [SIDE_LEN, SIDE_LEN]);
+
+        let mut sqs = [EMPTY; NUM_SQS];
+        for ((x, y), elem) in board.indexed_iter() {
+            sqs[x * SIDE_LEN + y] = *elem;
+        }
+
+        Self { sqs }
+    }
+
+    pub fn to_array(&self) -> Array2<u8> {
+        Array::from_shape_fn((SIDE_LEN, SIDE_LEN), |(x, y)| self.sqs[x * SIDE_LEN + y])
+    }
+
+    /// All the squares, by index.
+    pub fn sqs(&self) -> &[u8; NUM_SQS] {
+        &self.sqs
+    }
+
+    /// Element at the given square.
+    #[inline(always)]
+    pub fn get(&self, sq: Sq) -> u8 {
+        self.sqs[sq as usize]
+    }
+
+    /// Sets the given square on the board to the given color.
+    pub fn set_sq(&mut self, color: u8, sq: Sq) {
+        assert!(color == BLACK || color == WHITE);
+        assert_eq!(self.sqs[sq as usize], EMPTY);
+        self.sqs[sq as usize] = color;
+    }
+
+    /// Clears the given square on the board of the given color.
+    pub fn clear_sq(&mut self, color: u8, sq: Sq) {
+        assert!(color == BLACK || color == WHITE);
+        assert_eq!(self.sqs[sq as usize], color);
+        self.sqs[sq as usize] = EMPTY;
+    }
+
+    /// Check if the (color specific) pattern matches, starting at the given square, along direction d,
+    /// reading the pattern backwards if reversed. The pattern must fit on the board.
+    #[inline(always)]
+    fn matches_at(&self, pattern: &[u8], start: Sq, d: usize, reversed: bool) -> bool {
+        let length = pattern.len();
+        let offset = DIR_OFFSETS[d];
+        let mut sq = start as isize;
+
+        for k in 0..length {
+            let p_val = if reversed { pattern[length - 1 - k] } else { pattern[k] };
+            if p_val & self.sqs[sq as usize] == 0 {
+                return false;
+            }
+            sq += offset;
+        }
+
+        true
+    }
+
+    /// Match starting at the given square, along direction d.
+    #[inline(always)]
+    fn to_match(start: Sq, d: usize, length: usize) -> Match {
+        (to_point(start), to_point(step(start, d, length as isize - 1)))
+    }
+
+    /// Append the match of the (color specific) pattern on the segment starting at the given square, along direction d,
+    /// in either orientation (only once, if the pattern is symmetric). Same as pattern_search::push_segment_matches.
+    #[inline(always)]
+    fn push_segment_matches(&self, pattern: &[u8], symmetric: bool, start: Sq, d: usize, matches: &mut Vec<Match>) {
+        if self.matches_at(pattern, start, d, false) {
+            matches.push(Self::to_match(start, d, pattern.len()));
+        } else if !symmetric && self.matches_at(pattern, start, d, true) {
+            let (a, b) = Self::to_match(start, d, pattern.len());
+            matches.push((b, a));
+        }
+    }
+
+    /// Search for a (color specific) 1d pattern on the board. Same as pattern_search::search_board_into.
+    ///
+    /// Segments are scanned along the first NUM_AXES directions only, in both orientations, so there are no duplicates.
+    pub fn search_board_into(&self, pattern: &[u8], matches: &mut Vec<Match>) {
+        let length = pattern.len();
+        let symmetric = is_symmetric(pattern);
+        // Most squares are ruled out by the ends of the pattern.
+        let ends = pattern[0] | pattern[length - 1];
+
+        for (sq, steps) in STEPS.iter().enumerate() {
+            if ends & self.sqs[sq] != 0 {
+                for (d, n) in steps.iter().enumerate().take(NUM_AXES) {
+                    if *n as usize + 1 >= length {
+                        self.push_segment_matches(pattern, symmetric, sq as Sq, d, matches);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Search for a (color specific) 1d pattern on the board including the given square. Same as pattern_search::search_point_into.
+    pub fn search_point_into(&self, pattern: &[u8], sq: Sq, matches: &mut Vec<Match>) {
+        let length = pattern.len() as isize;
+        let symmetric = is_symmetric(pattern);
+        let steps = &STEPS[sq as usize];
+
+        for d in 0..NUM_AXES {
+            let fwd = steps[d] as isize;
+            let bwd = steps[d + NUM_AXES] as isize;
+
+            // The square is at h from the start of the segment.
+            for h in 0..length.min(bwd + 1) {
+                if length - 1 - h <= fwd {
+                    self.push_segment_matches(pattern, symmetric, step(sq, d, -h), d, matches);
+                }
+            }
+        }
+    }
+
+    /// Search for a (color specific) 1d pattern on the board including the given square as an own_sq.
+    /// Same as pattern_search::search_point_own_into.
+    pub fn search_point_own_into(&self, pattern: &[u8], color: u8, sq: Sq, own_sqs: &[isize], matches: &mut Vec<Match>) {
+        if self.get(sq) != color {
+            return;
+        }
+
+        let length = pattern.len() as isize;
+        let last = length - 1;
+        let steps = &STEPS[sq as usize];
+        let fits = |d: usize, h: isize| h <= steps[d + NUM_AXES] as isize && last - h <= steps[d] as isize;
+
+        for d in 0..NUM_AXES {
+            // The square is at own_sq from the start of the pattern, and the pattern lies along d in the forward orientation,
+            // and against it in the reverse orientation.
+            for own_sq in own_sqs.iter().copied() {
+                let start = step(sq, d, -own_sq);
+                if fits(d, own_sq) && self.matches_at(pattern, start, d, false) {
+                    matches.push(Self::to_match(start, d, length as usize));
+                }
+            }
+
+            for own_sq in own_sqs.iter().copied() {
+                let h = last - own_sq;
+                if fits(d, h) {
+                    let start = step(sq, d, -h);
+                    let found_fwd = own_sqs.contains(&h) && self.matches_at(pattern, start, d, false);
+
+                    if !found_fwd && self.matches_at(pattern, start, d, true) {
+                        let (a, b) = Self::to_match(start, d, length as usize);
+                        matches.push((b, a));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Search for a 1d pattern on the board. Same as pattern_search::search_board.
+    pub fn search_board(&self, gen_pattern: &[u8], color: u8) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        self.search_board_into(&get_pattern(gen_pattern, color), &mut matches);
+        matches
+    }
+
+    /// Search for a 1d pattern on the board including the given square. Same as pattern_search::search_point.
+    pub fn search_point(&self, gen_pattern: &[u8], color: u8, sq: Sq) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        self.search_point_into(&get_pattern(gen_pattern, color), sq, &mut matches);
+        matches
+    }
+
+    /// Search for a 1d pattern on the board including the given square as an own_sq. Same as pattern_search::search_point_own.
+    pub fn search_point_own(&self, gen_pattern: &[u8], color: u8, sq: Sq, own_sqs: &[isize]) -> Vec<Match> {
+        let mut matches: Vec<Match> = Vec::new();
+        self.search_point_own_into(&get_pattern(gen_pattern, color), color, sq, own_sqs, &mut matches);
+        matches
+    }
+
+    /// Get all pattern matches on the board. Same as pattern::search_all_board.
+    pub fn search_all_board(&self, color: u8, pri: ThreatPri) -> Vec<Threat> {
+        let mut threats: Vec<Threat> = Vec::new();
+        let mut matches: Vec<Match> = Vec::new();
+
+        for p in PATTERNS_BY_PRI[&pri] {
+            matches.clear();
+            self.search_board_into(p.for_color(color), &mut matches);
+            threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+        }
+
+        threats
+    }
+
+    /// Get all pattern matches including the given square. Same as pattern::search_all_point.
+    pub fn search_all_point(&self, color: u8, sq: Sq, pri: ThreatPri) -> Vec<Threat> {
+        let mut threats: Vec<Threat> = Vec::new();
+        let mut matches: Vec<Match> = Vec::new();
+
+        for p in PATTERNS_BY_PRI[&pri] {
+            matches.clear();
+            self.search_point_into(p.for_color(color), sq, &mut matches);
+            threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+        }
+
+        threats
+    }
+
+    /// Get all pattern matches including the given square as an own_sq. Same as pattern::search_all_point_own.
+    pub fn search_all_point_own(&self, color: u8, sq: Sq, pri: ThreatPri) -> Vec<Threat> {
+        let mut threats: Vec<Threat> = Vec::new();
+        let mut matches: Vec<Match> = Vec::new();
+
+        for p in PATTERNS_BY_PRI[&pri] {
+            matches.clear();
+            self.search_point_own_into(p.for_color(color), color, sq, &p.own_sqs, &mut matches);
+            threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+        }
+
+        threats
+    }
+}
@@ -1,17 +1,30 @@
+pub mod bitboard;
 pub mod board;
 pub mod consts;
+pub mod defence;
+pub mod engine;
+pub mod evaluation;
+pub mod fixed_board;
 pub mod geometry;
+pub mod move_analysis;
 pub mod pattern;
+pub mod pattern_analysis;
 pub mod pattern_search;
+pub mod pattern_table;
+pub mod pn_search;
+pub mod simd_search;
 pub mod state;
 pub mod testing;
 pub mod threat_space_search;
+pub mod threat_tracker;
 pub mod todos;
 
 use consts::{BLACK, WHITE};
 use state::get_state;
 use std::time::Instant;
-use threat_space_search::{animate_variation, potential_win_variations, tss_board, variation_to_algebraic};
+use threat_space_search::{
+    animate_variation, potential_win_variations, reduce_variations, tss_board, tss_board_limited, variation_to_algebraic, TSSLimits, TSSMode,
+};
 
 fn main() {
     // // NOTE: WHITE is clearly winning here, but TSS finds a win for black
@@ -176,8 +189,10 @@ fn main() {
 
     println!("Time taken: {} seconds", (start.elapsed().as_nanos() as f32) / 1e9);
 
-    let node = tss_board(&mut s.board, s.turn);
-    let potential_win_vars = potential_win_variations(&node);
+    let result = tss_board_limited(&mut s.board, s.turn, TSSMode::VCT, &TSSLimits::default());
+    println!("{}", result.stats);
+
+    let potential_win_vars = reduce_variations(&potential_win_variations(&result.tree));
     println!("{}", potential_win_vars.len());
     for v in potential_win_vars.iter() {
         println!("{:?}", variation_to_algebraic(v));
@@ -0,0 +1,231 @@
+//! Analysis of a (candidate) move: the threats it creates, strengthens, weakens or destroys, and the double threats (forks) it creates.
+
+use crate::board::{point_to_algebraic, set_sq};
+use crate::consts::{ACT_ELEMS_TO_NAMES, BLACK, EMPTY, WHITE};
+use crate::geometry::{point_set_on_line, slope_intercept, Point};
+use crate::pattern::{search_all_point, Pattern, Threat, ThreatPri, PATTERNS};
+use fnv::FnvHashSet;
+use ndarray::prelude::*;
+use std::fmt;
+
+/// Effect of a move on a threat.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MoveEffect {
+    Created,
+    Strengthened,
+    Weakened,
+    Destroyed,
+}
+
+/// A threat (of either color) affected by a move.
+///
+/// threat is the threat after the move, except when it's Destroyed, in which case it's the threat before the move.
+/// previous is the threat (on the same line, on the same stones) that was Strengthened or Weakened into threat.
+#[derive(Clone, Debug)]
+pub struct ThreatChange {
+    pub color: u8,
+    pub effect: MoveEffect,
+    pub pattern: &'static Pattern,
+    pub threat: Threat,
+    pub previous: Option<Threat>,
+}
+
+impl fmt::Display for ThreatChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.threat.m;
+        write!(
+            f,
+            "{} {:?} {} ({}-{})",
+            ACT_ELEMS_TO_NAMES[&self.color],
+            self.effect,
+            self.pattern.name,
+            point_to_algebraic(m.0),
+            point_to_algebraic(m.1)
+        )?;
+
+        if let Some(x) = &self.previous {
+            write!(f, " from {}", PATTERNS[x.pidx].name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Check if two threats are the same, i.e., the same pattern on the same segment, in either orientation.
+#[inline(always)]
+fn same_threat(a: &Threat, b: &Threat) -> bool {
+    a.pidx == b.pidx && (a.m == b.m || (a.m.0 == b.m.1 && a.m.1 == b.m.0))
+}
+
+/// OWN squares of the threat.
+fn own_sqs(threat: &Threat) -> FnvHashSet<Point> {
+    point_set_on_line(threat.m.0, threat.m.1, &PATTERNS[threat.pidx].own_sqs)
+}
+
+/// Check if two threats lie on the same line, and the stones of one are a subset of those of the other.
+fn related(a: &Threat, b: &Threat) -> bool {
+    if slope_intercept(a.m.0, a.m.1) != slope_intercept(b.m.0, b.m.1) {
+        return false;
+    }
+
+    let (x, y) = (own_sqs(a), own_sqs(b));
+    x.is_subset(&y) || y.is_subset(&x)
+}
+
+/// Check if the threat is stronger than the other, i.e., it has a lower defcon, or the same defcon and it's Immediate.
+fn stronger(a: &Threat, b: &Threat) -> bool {
+    a.defcon < b.defcon || (a.defcon == b.defcon && PATTERNS[a.pidx].immediate && !PATTERNS[b.pidx].immediate)
+}
+
+/// Threats of both colors, including the given point, that are affected by color playing there.
+///
+/// A threat found after the move is Strengthened (or Weakened) if it replaces a weaker (or stronger) related threat,
+/// i.e., one on the same line with a subset or a superset of its stones, and Created otherwise.
+/// A threat lost by the move is Destroyed if it isn't replaced by any related threat.
+/// The changes of color are listed first.
+pub fn classify_move(board: &Array2<u8>, color: u8, point: Point) -> Vec<ThreatChange> {
+    assert_eq!(board[(point.0 as usize, point.1 as usize)], EMPTY);
+
+    let mut next_board = board.to_owned();
+    set_sq(&mut next_board, color, point);
+
+    let mut changes: Vec<ThreatChange> = Vec::new();
+    let colors = if color == BLACK { [BLACK, WHITE] } else { [WHITE, BLACK] };
+
+    for c in colors {
+        let before = search_all_point(board, c, point, ThreatPri::All);
+        let after = search_all_point(&next_board, c, point, ThreatPri::All);
+
+        let lost = before
+            .iter()
+            .filter(|x| !after.iter().any(|y| same_threat(x, y)))
+            .collect::<Vec<&Threat>>();
+        let found = after
+            .iter()
+            .filter(|x| !before.iter().any(|y| same_threat(x, y)))
+            .collect::<Vec<&Threat>>();
+
+        for a in found.iter() {
+            // The strongest of the related threats is the one that's replaced.
+            let previous = lost
+                .iter()
+                .filter(|b| related(a, b))
+                .fold(None, |x: Option<&Threat>, b| match x {
+                    Some(y) if !stronger(b, y) => Some(y),
+                    _ => Some(b),
+                })
+                .cloned();
+
+            let effect = match &previous {
+                None => MoveEffect::Created,
+                Some(b) if stronger(b, a) => MoveEffect::Weakened,
+                Some(_) => MoveEffect::Strengthened,
+            };
+
+            changes.push(ThreatChange {
+                color: c,
+                effect,
+                pattern: PATTERNS[a.pidx],
+                threat: (*a).clone(),
+                previous,
+            });
+        }
+
+        for b in lost.iter().filter(|b| !found.iter().any(|a| related(a, b))) {
+            changes.push(ThreatChange {
+                color: c,
+                effect: MoveEffect::Destroyed,
+                pattern: PATTERNS[b.pidx],
+                threat: (*b).clone(),
+                previous: None,
+            });
+        }
+    }
+
+    changes
+}
+
+/// Kind of fork, strongest first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ForkKind {
+    FourFour,
+    FourThree,
+    ThreeThree,
+}
+
+/// A fork: two threats created by a move, that can't be defended against by a single move,
+/// i.e., threats whose critical squares don't overlap.
+#[derive(Clone, Debug)]
+pub struct Fork {
+    pub kind: ForkKind,
+    pub threats: (Threat, Threat),
+}
+
+/// Check if the threat is a four.
+#[inline(always)]
+fn is_four(threat: &Threat) -> bool {
+    threat.defcon == 1
+}
+
+/// Check if the threat is a three, i.e., an Immediate threat of defcon 2.
+#[inline(always)]
+fn is_three(threat: &Threat) -> bool {
+    threat.defcon == 2 && PATTERNS[threat.pidx].immediate
+}
+
+/// Fours and threes including the given point, after color plays there.
+pub fn fork_threats(board: &Array2<u8>, color: u8, point: Point) -> Vec<Threat> {
+    assert_eq!(board[(point.0 as usize, point.1 as usize)], EMPTY);
+
+    let mut board = board.to_owned();
+    set_sq(&mut board, color, point);
+
+    search_all_point(&board, color, point, ThreatPri::Immediate)
+        .into_iter()
+        .filter(|x| is_four(x) || is_three(x))
+        .collect()
+}
+
+/// The strongest fork created by color playing at the given point, if any.
+pub fn find_fork(board: &Array2<u8>, color: u8, point: Point) -> Option<Fork> {
+    let threats = fork_threats(board, color, point);
+    let mut fork: Option<Fork> = None;
+
+    for (i, a) in threats.iter().enumerate() {
+        for b in threats[(i + 1)..].iter() {
+            if !a.critical_sqs.is_disjoint(&b.critical_sqs) {
+                continue;
+            }
+
+            let kind = match (is_four(a), is_four(b)) {
+                (true, true) => ForkKind::FourFour,
+                (false, false) => ForkKind::ThreeThree,
+                _ => ForkKind::FourThree,
+            };
+
+            if fork.as_ref().is_none_or(|x| kind < x.kind) {
+                // The four comes first in a four-three.
+                let threats = if is_four(b) && !is_four(a) {
+                    (b.clone(), a.clone())
+                } else {
+                    (a.clone(), b.clone())
+                };
+                fork = Some(Fork { kind, threats });
+            }
+        }
+    }
+
+    fork
+}
+
+/// All the moves of color that create a fork, along with the strongest fork created.
+pub fn fork_moves(board: &Array2<u8>, color: u8) -> Vec<(Point, Fork)> {
+    board
+        .indexed_iter()
+        .filter(|x| *x.1 == EMPTY)
+        .filter_map(|((x, y), _)| {
+            let point = (x as isize, y as isize);
+            find_fork(board, color, point).map(|f| (point, f))
+        })
+        .collect()
+}
@@ -1,16 +1,21 @@
 //! Define struct to represent threat patterns, and related functions (search etc.).
 
-use crate::consts::{EMPTY, GEN_ELEMS, GEN_ELEMS_TO_NAMES, MAX_DEFCON, MDFIT, NOT_OWN, OWN, SIDE_LEN, WALL_ENEMY};
+use crate::consts::{
+    BLACK, CRITICAL_SQ_CHR, EMPTY, GEN_ELEMS, GEN_ELEMS_TO_CHRS, GEN_ELEMS_TO_NAMES, MAX_PATTERN_LEN, MDFIT, NOT_OWN, OWN, PATTERNS_FILE_ENV,
+    WALL_ENEMY, WHITE, WIN_LENGTH,
+};
 use crate::geometry::{point_set_on_line, Point};
 use crate::pattern_search::{
-    defcon_from_degree, degree, one_step_from_straight_threat, search_board, search_board_next_sq, search_point, search_point_next_sq,
-    search_point_own, search_point_own_next_sq, Match,
+    defcon_from_degree, degree, get_pattern, one_step_from_straight_threat, search_board_into, search_board_next_sq_into, search_point_into,
+    search_point_next_sq_into, search_point_own_into, search_point_own_next_sq_into, search_point_set_own, Match, NSQMatch,
 };
 use fnv::FnvHashMap;
 use fnv::FnvHashSet;
 use lazy_static::lazy_static;
 use ndarray::prelude::*;
+use std::env;
 use std::fmt;
+use std::fs;
 
 /// Pattern: Used to represent threat patterns.
 #[derive(Clone, Debug)]
@@ -23,15 +28,33 @@ pub struct Pattern {
     pub empty_sqs: Vec<isize>,
     pub defcon: usize,
     pub immediate: bool,
+    /// The pattern specialized for BLACK and WHITE. See for_color.
+    pub specialized: [Vec<u8>; 2],
 }
 
 #[allow(clippy::collapsible_if)]
 impl Pattern {
     pub fn new(pattern: Vec<u8>, critical_sqs: Vec<isize>, name: String, index: usize) -> Self {
+        Self::try_new(pattern, critical_sqs, name, index).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as new, returning an error (instead of panicking) if the pattern is invalid.
+    pub fn try_new(pattern: Vec<u8>, critical_sqs: Vec<isize>, name: String, index: usize) -> Result<Self, String> {
+        // Check size of name.
+        if name.is_empty() {
+            return Err(String::from("Empty pattern name!"));
+        }
+
         // Make sure elemnts of the pattern are valid.
         for elem in pattern.iter() {
-            assert!(GEN_ELEMS.iter().any(|&x| x == *elem));
-            assert!(*elem == OWN || (*elem & OWN == 0));
+            if !GEN_ELEMS.contains(elem) || (*elem != OWN && (*elem & OWN != 0)) {
+                return Err(format!("Invalid element {} in pattern {}!", elem, name));
+            }
+        }
+
+        let length = pattern.len();
+        if !(WIN_LENGTH..=MAX_PATTERN_LEN).contains(&length) {
+            return Err(format!("Invalid length {} of pattern {}!", length, name));
         }
 
         // Critical Squares are the places where if the oppenent plays,
@@ -40,20 +63,17 @@ impl Pattern {
         let mut critical_sqs_new = critical_sqs.to_owned();
         critical_sqs_new.sort();
         critical_sqs_new.dedup();
-        assert_eq!(critical_sqs, critical_sqs_new);
-
-        let length = pattern.len();
-        assert!(length <= SIDE_LEN);
+        if critical_sqs != critical_sqs_new {
+            return Err(format!("critical_sqs of pattern {} must be sorted and unique!", name));
+        }
 
         for sq in critical_sqs.iter() {
             // sq must be EMPTY for it to be critical.
-            let squ = *sq as usize;
-            assert!((0..length).contains(&squ) && pattern[squ] == EMPTY);
+            if !(0..length as isize).contains(sq) || pattern[*sq as usize] != EMPTY {
+                return Err(format!("Critical square {} of pattern {} must be EMPTY!", sq, name));
+            }
         }
 
-        // Check size of name.
-        assert!(!name.is_empty());
-
         // Check that any OWN or EMPTY squares in the pattern are contiguous,
         // i.e., OWN/EMPTY is not interrupted by any other kind of square.
         // This is what a normal/useful pattern would like.
@@ -65,7 +85,9 @@ impl Pattern {
                     oe_start = true;
                 }
 
-                assert!(!oe_end, "Non-contiguous OWN/EMPTY squares!");
+                if oe_end {
+                    return Err(format!("Non-contiguous OWN/EMPTY squares in pattern {}!", name));
+                }
             } else {
                 if oe_start && !oe_end {
                     oe_end = true;
@@ -83,7 +105,7 @@ impl Pattern {
         let other_empty_sqs = pattern
             .iter()
             .enumerate()
-            .filter(|x| *x.1 == EMPTY && !critical_sqs.iter().any(|&y| y == (x.0 as isize)))
+            .filter(|x| *x.1 == EMPTY && !critical_sqs.contains(&(x.0 as isize)))
             .map(|x| x.0 as isize)
             .collect::<Vec<isize>>();
 
@@ -92,21 +114,26 @@ impl Pattern {
 
         let defcon = defcon_from_degree(degree(&pattern));
 
-        let immediate = if defcon < 2 { true } else { one_step_from_straight_threat(&pattern) };
-
-        // Checks on data fields.
-        assert!(!pattern.is_empty());
-        assert!((0..=MAX_DEFCON).contains(&defcon));
+        // A straight four doesn't fit in a pattern of length WIN_LENGTH.
+        let immediate = if defcon < 2 {
+            true
+        } else {
+            length > WIN_LENGTH && one_step_from_straight_threat(&pattern)
+        };
 
         // Check on empty_sqs that they need to be useful.
         let curr_degree = degree(&pattern);
         for esq in empty_sqs.iter() {
             let mut next_pattern = pattern.to_owned();
             next_pattern[*esq as usize] = OWN;
-            assert_eq!(degree(&next_pattern), curr_degree + 1);
+            if degree(&next_pattern) != curr_degree + 1 {
+                return Err(format!("EMPTY square {} of pattern {} doesn't bring it closer to five!", esq, name));
+            }
         }
 
-        Self {
+        let specialized = [get_pattern(&pattern, BLACK), get_pattern(&pattern, WHITE)];
+
+        Ok(Self {
             pattern,
             critical_sqs,
             own_sqs,
@@ -115,7 +142,61 @@ impl Pattern {
             empty_sqs,
             defcon,
             immediate,
+            specialized,
+        })
+    }
+
+    /// The pattern specialized for the given color. Same as get_pattern, without the allocation.
+    #[inline(always)]
+    pub fn for_color(&self, color: u8) -> &[u8] {
+        match color {
+            BLACK => &self.specialized[0],
+            WHITE => &self.specialized[1],
+            _ => panic!("Invalid color!"),
+        }
+    }
+}
+
+impl Pattern {
+    /// Pattern from its text representation: one character per element (see GEN_ELEMS_TO_CHRS),
+    /// with critical squares (which are EMPTY) marked by CRITICAL_SQ_CHR. For example, "|xxxx*" is P_4_A.
+    pub fn from_text(text: &str, name: String, index: usize) -> Self {
+        Self::try_from_text(text, name, index).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as from_text, returning an error (instead of panicking) if the text isn't a valid pattern.
+    pub fn try_from_text(text: &str, name: String, index: usize) -> Result<Self, String> {
+        let mut pattern: Vec<u8> = Vec::new();
+        let mut critical_sqs: Vec<isize> = Vec::new();
+
+        for (i, c) in text.chars().enumerate() {
+            if c == CRITICAL_SQ_CHR {
+                pattern.push(EMPTY);
+                critical_sqs.push(i as isize);
+            } else {
+                match GEN_ELEMS_TO_CHRS.iter().find(|x| *x.1 == c) {
+                    Some(x) => pattern.push(*x.0),
+                    None => return Err(format!("Invalid character '{}' in pattern {}!", c, name)),
+                }
+            }
         }
+
+        Self::try_new(pattern, critical_sqs, name, index)
+    }
+
+    /// Text representation of the pattern. See from_text.
+    pub fn to_text(&self) -> String {
+        self.pattern
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                if self.critical_sqs.contains(&(i as isize)) {
+                    CRITICAL_SQ_CHR
+                } else {
+                    GEN_ELEMS_TO_CHRS[x]
+                }
+            })
+            .collect()
     }
 }
 
@@ -141,12 +222,116 @@ impl fmt::Display for Pattern {
     }
 }
 
+/// Error in a pattern set, at the given line of its text representation (if it's specific to a line).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternSetError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for PatternSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(x) => write!(f, "line {}: {}", x, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// A set of patterns, where the index of each pattern is its position in the set.
+#[derive(Clone, Debug)]
+pub struct PatternSet {
+    pub patterns: Vec<Pattern>,
+}
+
+impl PatternSet {
+    pub fn new(patterns: Vec<Pattern>) -> Self {
+        Self::try_new(patterns).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as new, returning an error (instead of panicking) if the pattern set is invalid.
+    pub fn try_new(patterns: Vec<Pattern>) -> Result<Self, String> {
+        for (i, p) in patterns.iter().enumerate() {
+            if i != p.index {
+                return Err(format!("Pattern {} has index {}, but it's at position {}!", p.name, p.index, i));
+            }
+        }
+
+        let num_names = patterns.iter().map(|x| String::from(&x.name)).collect::<FnvHashSet<String>>().len();
+        if num_names != patterns.len() {
+            return Err(String::from("Pattern names must be unique!"));
+        }
+
+        let max_defcon_imm = patterns.iter().fold(usize::MIN, |a, b| if b.immediate { a.max(b.defcon) } else { a });
+        if max_defcon_imm != MDFIT {
+            return Err(format!(
+                "The max defcon of the Immediate patterns is {}, instead of {}!",
+                max_defcon_imm, MDFIT
+            ));
+        }
+
+        Ok(Self { patterns })
+    }
+
+    pub fn builtin() -> Self {
+        Self::new(BUILTIN_PATTERNS.iter().map(|&x| x.to_owned()).collect())
+    }
+
+    /// Pattern set from its text representation: one pattern per line, given by its name and its text representation
+    /// (see Pattern::from_text), separated by whitespace. Empty lines, and lines starting with "//" are ignored.
+    /// Errors in a pattern are reported along with the (1-based) number of its line.
+    pub fn from_text(text: &str) -> Result<Self, PatternSetError> {
+        let mut patterns: Vec<Pattern> = Vec::new();
+        let mut names: FnvHashSet<String> = FnvHashSet::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            let error = |message: String| PatternSetError { line: Some(i + 1), message };
+
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() != 2 {
+                return Err(error(format!("Expected a name and a pattern, found: {}", line)));
+            }
+            if !names.insert(String::from(fields[0])) {
+                return Err(error(format!("Duplicate pattern name: {}", fields[0])));
+            }
+
+            patterns.push(Pattern::try_from_text(fields[1], String::from(fields[0]), patterns.len()).map_err(error)?);
+        }
+
+        Self::try_new(patterns).map_err(|message| PatternSetError { line: None, message })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, PatternSetError> {
+        let text = fs::read_to_string(path).map_err(|e| PatternSetError {
+            line: None,
+            message: format!("Unable to read pattern set from {}: {}", path, e),
+        })?;
+        Self::from_text(&text)
+    }
+
+    /// Text representation of the pattern set. See from_text.
+    pub fn to_text(&self) -> String {
+        let width = self.patterns.iter().fold(0, |a, b| a.max(b.name.len()));
+        self.patterns
+            .iter()
+            .map(|x| format!("{:width$} {}\n", x.name, x.to_text(), width = width))
+            .collect()
+    }
+}
+
 /// Enum to represent the priority of a Threat.
+/// Four is the subset of Immediate threats with defcon <= 1, i.e., fours and fives.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ThreatPri {
     All,
     Immediate,
     NonImmediate,
+    Four,
 }
 
 lazy_static! {
@@ -253,49 +438,41 @@ lazy_static! {
         16
     );
 
-    /// All defined patterns.
-    pub static ref PATTERNS: Vec<&'static Pattern> = {
-        let patterns: [&'static Pattern; 17] = [
-            &(*P_WIN),
-            &(*P_4_ST),
-            &(*P_4_A),
-            &(*P_4_B),
-            &(*P_4_C),
-            &(*P_3_ST),
-            &(*P_3_A),
-            &(*P_3_B),
-            &(*P_3_C),
-            &(*P_3_D),
-            &(*P_3_E),
-            &(*P_3_F),
-            &(*P_3_G),
-            &(*P_3_H),
-            &(*P_2_A),
-            &(*P_2_B),
-            &(*P_2_C),
-        ];
-
-        for (i, p) in patterns.iter().enumerate() {
-            assert_eq!(i, p.index);
+    /// Built-in patterns.
+    pub static ref BUILTIN_PATTERNS: [&'static Pattern; 17] = [
+        &(*P_WIN),
+        &(*P_4_ST),
+        &(*P_4_A),
+        &(*P_4_B),
+        &(*P_4_C),
+        &(*P_3_ST),
+        &(*P_3_A),
+        &(*P_3_B),
+        &(*P_3_C),
+        &(*P_3_D),
+        &(*P_3_E),
+        &(*P_3_F),
+        &(*P_3_G),
+        &(*P_3_H),
+        &(*P_2_A),
+        &(*P_2_B),
+        &(*P_2_C),
+    ];
+
+    /// The pattern set in use: loaded from the file given by the PATTERNS_FILE_ENV environment variable if it's set,
+    /// and the built-in patterns otherwise. If the file can't be loaded, the error is reported and the built-in patterns are used.
+    pub static ref PATTERN_SET: PatternSet = match env::var(PATTERNS_FILE_ENV).map(|x| (PatternSet::from_file(&x), x)) {
+        Ok((Ok(x), _)) => x,
+        Ok((Err(e), path)) => {
+            eprintln!("Using the built-in patterns. Invalid pattern set in {}: {}", path, e);
+            PatternSet::builtin()
         }
-
-        let num_names = patterns
-            .iter()
-            .map(|&x| String::from(&x.name))
-            .collect::<FnvHashSet<String>>()
-            .len();
-        assert_eq!(num_names, patterns.len());
-
-        let max_defcon_imm =
-            patterns.iter().fold(
-                usize::MIN,
-                |a, b| if b.immediate { a.max(b.defcon) } else { a },
-            );
-        assert_eq!(max_defcon_imm, MDFIT);
-
-        Vec::from(patterns)
+        Err(_) => PatternSet::builtin(),
     };
 
+    /// All defined patterns.
+    pub static ref PATTERNS: Vec<&'static Pattern> = PATTERN_SET.patterns.iter().collect();
+
     pub static ref NUM_PTNS: usize = PATTERNS.len();
 
     /// Patterns by defcon.
@@ -303,9 +480,7 @@ lazy_static! {
         let mut m: FnvHashMap<usize, Vec<&'static Pattern>> = FnvHashMap::default();
 
         for p in PATTERNS.iter() {
-            m.entry(p.defcon)
-                .or_insert_with(Vec::<&'static Pattern>::new)
-                .push(p);
+            m.entry(p.defcon).or_default().push(p);
         }
 
         m
@@ -333,12 +508,18 @@ lazy_static! {
         PATTERNS.iter().filter(|x| !x.immediate).copied().collect::<Vec<&'static Pattern>>()
     };
 
+    /// Four PATTERNS.
+    pub static ref PATTERNS_F: Vec<&'static Pattern> = {
+        PATTERNS.iter().filter(|x| x.defcon <= 1).copied().collect::<Vec<&'static Pattern>>()
+    };
+
     /// Patterns by priority.
     pub static ref PATTERNS_BY_PRI: FnvHashMap<ThreatPri, &'static Vec<&'static Pattern>> = {
         let mut m: FnvHashMap<ThreatPri, &'static Vec<&'static Pattern>> = FnvHashMap::default();
         m.insert(ThreatPri::All, &PATTERNS);
         m.insert(ThreatPri::Immediate, &PATTERNS_I);
         m.insert(ThreatPri::NonImmediate, &PATTERNS_NI);
+        m.insert(ThreatPri::Four, &PATTERNS_F);
         m
     };
 }
@@ -367,10 +548,11 @@ impl Threat {
 pub fn search_all_board(board: &Array2<u8>, color: u8, pri: ThreatPri) -> Vec<Threat> {
     let mut threats: Vec<Threat> = Vec::new();
 
+    let mut matches: Vec<Match> = Vec::new();
     for p in PATTERNS_BY_PRI[&pri] {
-        for m in search_board(board, &p.pattern, color) {
-            threats.push(Threat::new(m, p));
-        }
+        matches.clear();
+        search_board_into(board, p.for_color(color), &mut matches);
+        threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
     }
 
     threats
@@ -380,10 +562,11 @@ pub fn search_all_board(board: &Array2<u8>, color: u8, pri: ThreatPri) -> Vec<Th
 pub fn search_all_point(board: &Array2<u8>, color: u8, point: Point, pri: ThreatPri) -> Vec<Threat> {
     let mut threats: Vec<Threat> = Vec::new();
 
+    let mut matches: Vec<Match> = Vec::new();
     for p in PATTERNS_BY_PRI[&pri] {
-        for m in search_point(board, &p.pattern, color, point) {
-            threats.push(Threat::new(m, p));
-        }
+        matches.clear();
+        search_point_into(board, p.for_color(color), point, &mut matches);
+        threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
     }
 
     threats
@@ -393,8 +576,22 @@ pub fn search_all_point(board: &Array2<u8>, color: u8, point: Point, pri: Threat
 pub fn search_all_point_own(board: &Array2<u8>, color: u8, point: Point, pri: ThreatPri) -> Vec<Threat> {
     let mut threats: Vec<Threat> = Vec::new();
 
+    let mut matches: Vec<Match> = Vec::new();
     for p in PATTERNS_BY_PRI[&pri] {
-        for m in search_point_own(board, &p.pattern, color, point, &p.own_sqs) {
+        matches.clear();
+        search_point_own_into(board, p.for_color(color), color, point, &p.own_sqs, &mut matches);
+        threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+    }
+
+    threats
+}
+
+/// Get all pattern matches whose own_sqs are all drawn from the given set of points.
+pub fn search_all_point_set_own(board: &Array2<u8>, color: u8, points: &FnvHashSet<Point>, pri: ThreatPri) -> Vec<Threat> {
+    let mut threats: Vec<Threat> = Vec::new();
+
+    for p in PATTERNS_BY_PRI[&pri] {
+        for m in search_point_set_own(board, &p.pattern, color, points, &p.own_sqs) {
             threats.push(Threat::new(m, p));
         }
     }
@@ -406,11 +603,11 @@ pub fn search_all_point_own(board: &Array2<u8>, color: u8, point: Point, pri: Th
 pub fn search_all_board_get_next_sqs(board: &Array2<u8>, color: u8, pri: ThreatPri) -> FnvHashSet<Point> {
     let mut nsqs: FnvHashSet<Point> = FnvHashSet::default();
 
+    let mut pairs: Vec<NSQMatch> = Vec::new();
     for p in PATTERNS_BY_PRI[&pri] {
-        for x in search_board_next_sq(board, &p.pattern, color) {
-            nsqs.insert(x.0);
-        }
+        search_board_next_sq_into(board, p.for_color(color), color, &mut pairs);
     }
+    nsqs.extend(pairs.iter().map(|x| x.0));
 
     nsqs
 }
@@ -419,11 +616,11 @@ pub fn search_all_board_get_next_sqs(board: &Array2<u8>, color: u8, pri: ThreatP
 pub fn search_all_point_get_next_sqs(board: &Array2<u8>, color: u8, point: Point, pri: ThreatPri) -> FnvHashSet<Point> {
     let mut nsqs: FnvHashSet<Point> = FnvHashSet::default();
 
+    let mut pairs: Vec<NSQMatch> = Vec::new();
     for p in PATTERNS_BY_PRI[&pri] {
-        for x in search_point_next_sq(board, &p.pattern, color, point) {
-            nsqs.insert(x.0);
-        }
+        search_point_next_sq_into(board, p.for_color(color), color, point, &mut pairs);
     }
+    nsqs.extend(pairs.iter().map(|x| x.0));
 
     nsqs
 }
@@ -432,11 +629,11 @@ pub fn search_all_point_get_next_sqs(board: &Array2<u8>, color: u8, point: Point
 pub fn search_all_point_own_get_next_sqs(board: &Array2<u8>, color: u8, point: Point, pri: ThreatPri) -> FnvHashSet<Point> {
     let mut nsqs: FnvHashSet<Point> = FnvHashSet::default();
 
+    let mut pairs: Vec<NSQMatch> = Vec::new();
     for p in PATTERNS_BY_PRI[&pri] {
-        for x in search_point_own_next_sq(board, &p.pattern, color, point, &p.own_sqs) {
-            nsqs.insert(x.0);
-        }
+        search_point_own_next_sq_into(board, p.for_color(color), color, point, &p.own_sqs, &mut pairs);
     }
+    nsqs.extend(pairs.iter().map(|x| x.0));
 
     nsqs
 }
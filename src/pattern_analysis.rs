@@ -0,0 +1,276 @@
+//! Analysis of threat patterns: derivation of critical squares, and a completeness check of a pattern set.
+//!
+//! A critical square of a pattern is an EMPTY square where the defender can refute the threat.
+//! The defender's placement on an EMPTY square doesn't refute the threat if, using only the OWN/EMPTY squares of the pattern,
+//! the attacker can still reach five in defcon moves, and in case of an immediate threat with defcon >= 2,
+//! can still reach a straight four in defcon - 1 moves (which is what makes the threat immediate).
+//! A two (a pattern of defcon MDFIT + 1) is held to the same straight four rule, since what it threatens is an open three.
+
+use crate::consts::{EMPTY, ENEMY, GEN_ELEMS_TO_CHRS, MAX_DEFCON, MDFIT, OWN, WALL, WIN_LENGTH};
+use crate::pattern::Pattern;
+use crate::pattern_search::degree;
+use std::fmt;
+
+/// Check if five can be reached (using only the OWN/EMPTY squares) in the given number of moves.
+pub fn can_reach_five(gen_pattern: &[u8], moves: usize) -> bool {
+    gen_pattern.len() >= WIN_LENGTH && degree(gen_pattern) + moves >= WIN_LENGTH
+}
+
+/// Check if a straight four (WIN_LENGTH - 1 OWN's in a row, with an EMPTY square on either side)
+/// can be reached (using only the OWN/EMPTY squares) in the given number of moves.
+pub fn can_reach_straight_four(gen_pattern: &[u8], moves: usize) -> bool {
+    let l = WIN_LENGTH + 1;
+
+    gen_pattern.len() >= l
+        && gen_pattern.windows(l).any(|w| {
+            let inner = &w[1..(l - 1)];
+            w[0] == EMPTY
+                && w[l - 1] == EMPTY
+                && inner.iter().all(|&x| x == OWN || x == EMPTY)
+                && inner.iter().filter(|&&x| x == EMPTY).count() <= moves
+        })
+}
+
+/// Derive the critical squares of a generic pattern with the given defcon, by trying the defender's placement on each EMPTY square.
+pub fn derive_critical_sqs(gen_pattern: &[u8], defcon: usize, immediate: bool) -> Vec<isize> {
+    // A two threatens to become a three (i.e., a straight four in one more move), whether it's flagged as immediate or not.
+    let needs_straight_four = defcon >= 2 && (immediate || defcon == MDFIT + 1);
+    let mut critical_sqs: Vec<isize> = Vec::new();
+
+    for (i, x) in gen_pattern.iter().enumerate() {
+        if *x == EMPTY {
+            let mut defended = gen_pattern.to_owned();
+            defended[i] = ENEMY;
+
+            let preserved = can_reach_five(&defended, defcon) && (!needs_straight_four || can_reach_straight_four(&defended, defcon - 1));
+
+            if !preserved {
+                critical_sqs.push(i as isize);
+            }
+        }
+    }
+
+    critical_sqs
+}
+
+/// A pattern whose critical squares differ from the derived ones.
+#[derive(Clone, Debug)]
+pub struct CriticalSqsMismatch {
+    pub name: String,
+    pub given: Vec<isize>,
+    pub derived: Vec<isize>,
+}
+
+impl fmt::Display for CriticalSqsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: critical_sqs given: {:?}, derived: {:?}", self.name, self.given, self.derived)
+    }
+}
+
+/// Patterns whose (hand-written) critical squares disagree with the derived ones.
+pub fn critical_sqs_mismatches(patterns: &[&Pattern]) -> Vec<CriticalSqsMismatch> {
+    patterns
+        .iter()
+        .filter_map(|p| {
+            let derived = derive_critical_sqs(&p.pattern, p.defcon, p.immediate);
+            if derived != p.critical_sqs {
+                Some(CriticalSqsMismatch {
+                    name: p.name.to_owned(),
+                    given: p.critical_sqs.to_owned(),
+                    derived,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Kind of problem found by the completeness check.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Finding {
+    /// The threat (or its immediacy) isn't found by any pattern.
+    Missed,
+    /// The same threat (the same OWN squares) is matched more than once.
+    DoubleCounted,
+    /// The threat is found, but with the wrong defcon, immediacy or critical squares.
+    Misclassified,
+}
+
+/// A line configuration, along with the problem found with the pattern set on it.
+#[derive(Clone, Debug)]
+pub struct LineReport {
+    pub line: Vec<u8>,
+    pub finding: Finding,
+    pub detail: String,
+}
+
+impl fmt::Display for LineReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.line.iter().map(|x| GEN_ELEMS_TO_CHRS[x]).collect::<String>();
+        write!(f, "{:?} {}: {}", self.finding, text, self.detail)
+    }
+}
+
+/// A pattern match on a line: the OWN and critical squares are positions on the line.
+#[derive(Clone, Debug)]
+struct LineMatch {
+    pidx: usize,
+    defcon: usize,
+    immediate: bool,
+    own_sqs: Vec<isize>,
+    critical_sqs: Vec<isize>,
+}
+
+/// All matches of the patterns on the line, in either orientation.
+fn search_line(line: &[u8], patterns: &[&Pattern]) -> Vec<LineMatch> {
+    let n = line.len() as isize;
+    let mut matches: Vec<LineMatch> = Vec::new();
+
+    for p in patterns.iter() {
+        let length = p.pattern.len() as isize;
+
+        for start in 0..=(n - length) {
+            for reversed in [false, true] {
+                // Position on the line of the k'th square of the pattern.
+                let at = |k: isize| if reversed { start + length - 1 - k } else { start + k };
+
+                if (0..length).all(|k| p.pattern[k as usize] & line[at(k) as usize] != 0) {
+                    let mut own_sqs = p.own_sqs.iter().map(|&k| at(k)).collect::<Vec<isize>>();
+                    let mut critical_sqs = p.critical_sqs.iter().map(|&k| at(k)).collect::<Vec<isize>>();
+                    own_sqs.sort();
+                    critical_sqs.sort();
+
+                    // A symmetric pattern matches the same segment in both orientations.
+                    if !matches
+                        .iter()
+                        .any(|x| x.pidx == p.index && x.own_sqs == own_sqs && x.critical_sqs == critical_sqs)
+                    {
+                        matches.push(LineMatch {
+                            pidx: p.index,
+                            defcon: p.defcon,
+                            immediate: p.immediate,
+                            own_sqs,
+                            critical_sqs,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    matches
+}
+
+/// Check the patterns on a single line configuration against the brute force threat class of the line.
+///
+/// The true defcon is the number of moves needed to reach five, and the threat is immediate if a straight four
+/// can be reached in one move less. The true critical squares are derived as in derive_critical_sqs.
+/// The patterns' view of the line is given by the matches of the lowest defcon (only the immediate ones, if any),
+/// and their critical squares are the squares that defend against all of them.
+pub fn check_line(line: &[u8], patterns: &[&Pattern]) -> Vec<LineReport> {
+    let mut reports: Vec<LineReport> = Vec::new();
+    let mut report = |finding: Finding, detail: String| {
+        reports.push(LineReport {
+            line: line.to_owned(),
+            finding,
+            detail,
+        })
+    };
+
+    let moves = (0..=MDFIT).find(|&x| can_reach_five(line, x));
+    let all_matches = search_line(line, patterns);
+
+    let defcon = match moves {
+        // Nothing to check if there's a five already.
+        Some(0) => return reports,
+        // Only fours and threes are checked: a line where five can't be reached in MDFIT moves must not match them.
+        None => {
+            for m in all_matches.iter().filter(|x| x.defcon > 0 && x.defcon <= MDFIT) {
+                report(
+                    Finding::Misclassified,
+                    format!("{} matches, but the line has no four or three", patterns[m.pidx].name),
+                );
+            }
+            return reports;
+        }
+        Some(x) => x,
+    };
+    let immediate = defcon < 2 || can_reach_straight_four(line, defcon - 1);
+    let critical_sqs = derive_critical_sqs(line, defcon, immediate);
+
+    let p_defcon = all_matches.iter().fold(MAX_DEFCON + 1, |a, b| a.min(b.defcon));
+    let p_immediate = all_matches.iter().any(|x| x.defcon == p_defcon && x.immediate);
+    let matches = all_matches
+        .iter()
+        .filter(|x| x.defcon == p_defcon && x.immediate == p_immediate)
+        .collect::<Vec<&LineMatch>>();
+
+    if p_defcon > defcon || (p_defcon == defcon && immediate && !p_immediate) {
+        report(Finding::Missed, format!("defcon: {}, immediate: {}", defcon, immediate));
+        return reports;
+    }
+
+    if p_defcon < defcon || p_immediate != immediate {
+        report(
+            Finding::Misclassified,
+            format!(
+                "defcon: {}, immediate: {}, but matched with defcon: {}, immediate: {}",
+                defcon, immediate, p_defcon, p_immediate
+            ),
+        );
+        return reports;
+    }
+
+    for (i, a) in matches.iter().enumerate() {
+        for b in matches[(i + 1)..].iter() {
+            if a.own_sqs == b.own_sqs {
+                let names = (&patterns[a.pidx].name, &patterns[b.pidx].name);
+                report(Finding::DoubleCounted, format!("{} and {} on own_sqs: {:?}", names.0, names.1, a.own_sqs));
+            }
+        }
+    }
+
+    let p_critical_sqs = matches[0]
+        .critical_sqs
+        .iter()
+        .filter(|x| matches.iter().all(|m| m.critical_sqs.contains(x)))
+        .copied()
+        .collect::<Vec<isize>>();
+
+    if p_critical_sqs != critical_sqs {
+        report(
+            Finding::Misclassified,
+            format!("critical_sqs: {:?}, but matched with critical_sqs: {:?}", critical_sqs, p_critical_sqs),
+        );
+    }
+
+    reports
+}
+
+/// Check the patterns against every line configuration: a run of between WIN_LENGTH and max_len EMPTY/OWN/ENEMY squares,
+/// closed on either side by a WALL or an ENEMY.
+pub fn check_completeness(patterns: &[&Pattern], max_len: usize) -> Vec<LineReport> {
+    let inner = [EMPTY, OWN, ENEMY];
+    let edges = [WALL, ENEMY];
+    let mut reports: Vec<LineReport> = Vec::new();
+
+    for len in WIN_LENGTH..=max_len {
+        for code in 0..inner.len().pow(len as u32) {
+            let run = (0..len)
+                .map(|k| inner[(code / inner.len().pow(k as u32)) % inner.len()])
+                .collect::<Vec<u8>>();
+
+            for left in edges.iter() {
+                for right in edges.iter() {
+                    let mut line = vec![*left];
+                    line.extend_from_slice(&run);
+                    line.push(*right);
+                    reports.extend(check_line(&line, patterns));
+                }
+            }
+        }
+    }
+
+    reports
+}
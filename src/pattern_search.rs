@@ -2,8 +2,9 @@
 
 #![allow(clippy::many_single_char_names)]
 
-use crate::consts::{BLACK, EMPTY, MAX_DEFCON, NUM_DIRECTIONS, OWN, STONE, WHITE, WIN_LENGTH};
-use crate::geometry::{increments, index_bounds, index_bounds_incl, Point};
+use crate::consts::{BLACK, EMPTY, MAX_DEFCON, NUM_AXES, NUM_DIRECTIONS, OWN, STONE, WHITE, WIN_LENGTH};
+use crate::geometry::{increments, index_bounds, index_bounds_incl, point_on_line, Point};
+use fnv::FnvHashSet;
 use ndarray::prelude::*;
 use std::cmp::max;
 
@@ -106,41 +107,79 @@ pub fn search_board(board: &Array2<u8>, gen_pattern: &[u8], color: u8) -> Vec<Ma
     matches
 }
 
-/// Search for a 1d pattern on a 2d board including the given point.
-pub fn search_point(board: &Array2<u8>, gen_pattern: &[u8], color: u8, point: Point) -> Vec<Match> {
-    let (x, y) = point;
+/// Squares of the line through the given point along axis a (walking in direction a),
+/// along with the position of the point on the line.
+pub fn line_at_point(board: &Array2<u8>, point: Point, a: usize) -> (Vec<u8>, usize) {
+    assert!(a < NUM_AXES);
 
-    let side = board.shape()[0];
-    let pattern = get_pattern(gen_pattern, color);
-    let length = pattern.len();
+    let side = board.shape()[0] as isize;
+    let (row_inc, col_inc) = increments(a);
+    let on_board = |p: Point| p.0 >= 0 && p.1 >= 0 && p.0 < side && p.1 < side;
 
-    let mut matches: Vec<Match> = Vec::new();
-    for d in 0..NUM_DIRECTIONS {
-        let (row_inc, col_inc) = increments(d);
-        let (s_min, s_max) = index_bounds_incl(side as isize, length as isize, x, y, row_inc, col_inc);
+    let mut start = point;
+    let mut pos: usize = 0;
+    while on_board((start.0 - row_inc, start.1 - col_inc)) {
+        start = (start.0 - row_inc, start.1 - col_inc);
+        pos += 1;
+    }
 
-        for h in s_min..s_max {
-            let (i, j) = (x + row_inc * h, y + col_inc * h);
+    let mut line: Vec<u8> = Vec::new();
+    let mut p = start;
+    while on_board(p) {
+        line.push(board[(p.0 as usize, p.1 as usize)]);
+        p = (p.0 + row_inc, p.1 + col_inc);
+    }
 
-            let mut found = true;
+    (line, pos)
+}
 
-            for k in 0..length {
-                if pattern[k] & board[(idx(i, row_inc, k) as usize, idx(j, col_inc, k) as usize)] == 0 {
-                    found = false;
-                    break;
-                }
-            }
+/// Search for a (color specific) 1d pattern on a line, including the given position, in either orientation.
+///
+/// Returns the (start, end) positions of each match, where start > end for matches in the reverse orientation.
+/// A segment that matches in both orientations is only recorded in the forward orientation.
+pub fn search_line_at(line: &[u8], pattern: &[u8], pos: usize) -> Vec<(usize, usize)> {
+    let n = line.len();
+    let length = pattern.len();
+    let mut matches: Vec<(usize, usize)> = Vec::new();
 
-            if found {
-                // Store Ordered Line Segment, a -> b, where the pattern lies.
-                let a = (i, j);
-                let b = (idx(i, row_inc, length - 1), idx(j, col_inc, length - 1));
-                matches.push((a, b));
-            }
+    if length > n || pos >= n {
+        return matches;
+    }
+
+    let symmetric = pattern.iter().eq(pattern.iter().rev());
+    let lo = pos.saturating_sub(length - 1);
+    let hi = pos.min(n - length);
+
+    for s in lo..=hi {
+        let cells = &line[s..(s + length)];
+
+        if pattern.iter().zip(cells.iter()).all(|(p, c)| p & c != 0) {
+            matches.push((s, s + length - 1));
+        } else if !symmetric && pattern.iter().rev().zip(cells.iter()).all(|(p, c)| p & c != 0) {
+            matches.push((s + length - 1, s));
         }
     }
 
-    dedupe_matches(&mut matches);
+    matches
+}
+
+/// Search for a 1d pattern on a 2d board including the given point, only along axis a.
+pub fn search_point_axis(board: &Array2<u8>, gen_pattern: &[u8], color: u8, point: Point, a: usize) -> Vec<Match> {
+    assert!(a < NUM_AXES);
+    let pattern = get_pattern(gen_pattern, color);
+    let symmetric = is_symmetric(&pattern);
+
+    let mut matches: Vec<Match> = Vec::new();
+    visit_axis_segments(board.shape()[0] as isize, pattern.len(), point, a, &mut |start, inc| {
+        push_segment_matches(board, &pattern, symmetric, start, inc, &mut matches);
+    });
+    matches
+}
+
+/// Search for a 1d pattern on a 2d board including the given point.
+pub fn search_point(board: &Array2<u8>, gen_pattern: &[u8], color: u8, point: Point) -> Vec<Match> {
+    let mut matches: Vec<Match> = Vec::new();
+    search_point_into(board, &get_pattern(gen_pattern, color), point, &mut matches);
     matches
 }
 
@@ -188,6 +227,26 @@ pub fn search_point_own(board: &Array2<u8>, gen_pattern: &[u8], color: u8, point
     matches
 }
 
+/// Search for a 1d pattern on a 2d board, such that all of its own_sqs are drawn from the given set of points.
+///
+/// Each match is searched for only from its smallest own_sq, so that it isn't found repeatedly.
+pub fn search_point_set_own(board: &Array2<u8>, gen_pattern: &[u8], color: u8, points: &FnvHashSet<Point>, own_sqs: &[isize]) -> Vec<Match> {
+    let mut matches: Vec<Match> = Vec::new();
+
+    for point in points.iter() {
+        for m in search_point_own(board, gen_pattern, color, *point, own_sqs) {
+            let m_own_sqs = own_sqs.iter().map(|x| point_on_line(m.0, m.1, *x)).collect::<Vec<Point>>();
+
+            if m_own_sqs.iter().all(|x| points.contains(x)) && m_own_sqs.iter().min() == Some(point) {
+                matches.push(m);
+            }
+        }
+    }
+
+    dedupe_matches(&mut matches);
+    matches
+}
+
 /// Remove duplicates from next_sq_match pairs.
 pub fn dedupe_next_sq_match_pairs(pairs: &mut Vec<NSQMatch>) {
     let mut i: usize = 0;
@@ -383,6 +442,248 @@ pub fn search_point_own_next_sq(board: &Array2<u8>, gen_pattern: &[u8], color: u
     next_sq_match_pairs
 }
 
+// Allocation-free variants of the above search functions.
+//
+// These take a pattern that's already specialized for a color (see Pattern::for_color), and append to a buffer owned by the caller.
+// Every line is scanned along the first NUM_AXES directions only, testing the pattern in both orientations at each segment,
+// so that a segment that matches in both orientations is recorded only once (in the forward orientation),
+// and no deduplication pass is needed.
+
+/// Check if the (color specific) pattern matches the segment starting at start, along inc,
+/// reading the pattern backwards if reversed.
+#[inline(always)]
+fn matches_segment(board: &Array2<u8>, pattern: &[u8], start: Point, inc: Point, reversed: bool) -> bool {
+    let length = pattern.len();
+
+    (0..length).all(|k| {
+        let p_val = if reversed { pattern[length - 1 - k] } else { pattern[k] };
+        p_val & board[(idx(start.0, inc.0, k) as usize, idx(start.1, inc.1, k) as usize)] != 0
+    })
+}
+
+/// Position (from start, along inc) of the next_sq, if the (color specific) pattern matches the segment starting at start,
+/// reading the pattern backwards if reversed, once a single EMPTY square (the next_sq) is filled by color.
+#[inline(always)]
+fn next_sq_segment(board: &Array2<u8>, pattern: &[u8], color: u8, start: Point, inc: Point, reversed: bool) -> Option<usize> {
+    let length = pattern.len();
+    let mut next_sq: Option<usize> = None;
+
+    for k in 0..length {
+        let p_val = if reversed { pattern[length - 1 - k] } else { pattern[k] };
+        let b_val = board[(idx(start.0, inc.0, k) as usize, idx(start.1, inc.1, k) as usize)];
+
+        if p_val & b_val == 0 {
+            if next_sq.is_none() && p_val == color && b_val == EMPTY {
+                next_sq = Some(k);
+            } else {
+                return None;
+            }
+        }
+    }
+
+    next_sq
+}
+
+/// Check if the pattern is the same in either orientation.
+#[inline(always)]
+pub fn is_symmetric(pattern: &[u8]) -> bool {
+    pattern.iter().eq(pattern.iter().rev())
+}
+
+/// Visit the starts of all the segments of the given length on the board, along the first NUM_AXES directions.
+#[inline(always)]
+fn visit_board_segments<F: FnMut(Point, Point)>(side: isize, length: usize, mut visit: F) {
+    for d in 0..NUM_AXES {
+        let (row_inc, col_inc) = increments(d);
+        let (row_min, row_max) = index_bounds(side, length as isize, row_inc);
+        let (col_min, col_max) = index_bounds(side, length as isize, col_inc);
+
+        for i in row_min..row_max {
+            for j in col_min..col_max {
+                visit((i, j), (row_inc, col_inc));
+            }
+        }
+    }
+}
+
+/// Visit the starts of all the segments of the given length including point, along axis a (in direction a).
+#[inline(always)]
+fn visit_axis_segments<F: FnMut(Point, Point)>(side: isize, length: usize, point: Point, a: usize, visit: &mut F) {
+    let (x, y) = point;
+    let (row_inc, col_inc) = increments(a);
+    let (s_min, s_max) = index_bounds_incl(side, length as isize, x, y, row_inc, col_inc);
+
+    for h in s_min..s_max {
+        visit((x + row_inc * h, y + col_inc * h), (row_inc, col_inc));
+    }
+}
+
+/// Visit the starts of all the segments of the given length including point, along the first NUM_AXES directions.
+#[inline(always)]
+fn visit_point_segments<F: FnMut(Point, Point)>(side: isize, length: usize, point: Point, mut visit: F) {
+    for a in 0..NUM_AXES {
+        visit_axis_segments(side, length, point, a, &mut visit);
+    }
+}
+
+/// Check if the segment of the given length starting at start, along inc, is on the board.
+#[inline(always)]
+fn segment_on_board(side: isize, length: usize, start: Point, inc: Point) -> bool {
+    let end = (idx(start.0, inc.0, length - 1), idx(start.1, inc.1, length - 1));
+    [start, end].iter().all(|p| p.0 >= 0 && p.1 >= 0 && p.0 < side && p.1 < side)
+}
+
+/// Append the match(es) of the pattern on the segment starting at start, along inc.
+#[inline(always)]
+fn push_segment_matches(board: &Array2<u8>, pattern: &[u8], symmetric: bool, start: Point, inc: Point, matches: &mut Vec<Match>) {
+    let length = pattern.len();
+    let end = (idx(start.0, inc.0, length - 1), idx(start.1, inc.1, length - 1));
+
+    if matches_segment(board, pattern, start, inc, false) {
+        matches.push((start, end));
+    } else if !symmetric && matches_segment(board, pattern, start, inc, true) {
+        matches.push((end, start));
+    }
+}
+
+/// Append the next_sq match(es) of the pattern on the segment starting at start, along inc.
+#[inline(always)]
+fn push_segment_next_sq_matches(board: &Array2<u8>, pattern: &[u8], color: u8, symmetric: bool, start: Point, inc: Point, pairs: &mut Vec<NSQMatch>) {
+    let length = pattern.len();
+    let end = (idx(start.0, inc.0, length - 1), idx(start.1, inc.1, length - 1));
+    let at = |k: usize| (idx(start.0, inc.0, k), idx(start.1, inc.1, k));
+
+    let fwd = next_sq_segment(board, pattern, color, start, inc, false);
+    if let Some(k) = fwd {
+        pairs.push((at(k), (start, end)));
+    }
+
+    if !symmetric {
+        if let Some(k) = next_sq_segment(board, pattern, color, start, inc, true) {
+            if fwd != Some(k) {
+                pairs.push((at(k), (end, start)));
+            }
+        }
+    }
+}
+
+/// Search for a (color specific) 1d pattern on a 2d board. Same as search_board.
+pub fn search_board_into(board: &Array2<u8>, pattern: &[u8], matches: &mut Vec<Match>) {
+    let symmetric = is_symmetric(pattern);
+    visit_board_segments(board.shape()[0] as isize, pattern.len(), |start, inc| {
+        push_segment_matches(board, pattern, symmetric, start, inc, matches);
+    });
+}
+
+/// Search for a (color specific) 1d pattern on a 2d board including the given point. Same as search_point.
+pub fn search_point_into(board: &Array2<u8>, pattern: &[u8], point: Point, matches: &mut Vec<Match>) {
+    let symmetric = is_symmetric(pattern);
+    visit_point_segments(board.shape()[0] as isize, pattern.len(), point, |start, inc| {
+        push_segment_matches(board, pattern, symmetric, start, inc, matches);
+    });
+}
+
+/// Search for a (color specific) 1d pattern on a 2d board including the given point as an own_sq. Same as search_point_own.
+pub fn search_point_own_into(board: &Array2<u8>, pattern: &[u8], color: u8, point: Point, own_sqs: &[isize], matches: &mut Vec<Match>) {
+    if board[(point.0 as usize, point.1 as usize)] != color {
+        return;
+    }
+
+    let side = board.shape()[0] as isize;
+    let length = pattern.len();
+    let last = length as isize - 1;
+
+    for d in 0..NUM_AXES {
+        let inc = increments(d);
+        let start_at = |h: isize| (point.0 - inc.0 * h, point.1 - inc.1 * h);
+
+        // The point is at own_sq from the start of the pattern, and the pattern lies along inc in the forward orientation,
+        // and against it in the reverse orientation.
+        for own_sq in own_sqs.iter() {
+            let start = start_at(*own_sq);
+            if segment_on_board(side, length, start, inc) && matches_segment(board, pattern, start, inc, false) {
+                matches.push((start, (idx(start.0, inc.0, length - 1), idx(start.1, inc.1, length - 1))));
+            }
+        }
+
+        for own_sq in own_sqs.iter() {
+            let h = last - own_sq;
+            let start = start_at(h);
+
+            if segment_on_board(side, length, start, inc) {
+                let found_fwd = own_sqs.contains(&h) && matches_segment(board, pattern, start, inc, false);
+
+                if !found_fwd && matches_segment(board, pattern, start, inc, true) {
+                    matches.push(((idx(start.0, inc.0, length - 1), idx(start.1, inc.1, length - 1)), start));
+                }
+            }
+        }
+    }
+}
+
+/// Search for a (color specific) 1d pattern on a 2d board, returning next_sq match pairs. Same as search_board_next_sq.
+pub fn search_board_next_sq_into(board: &Array2<u8>, pattern: &[u8], color: u8, pairs: &mut Vec<NSQMatch>) {
+    let symmetric = is_symmetric(pattern);
+    visit_board_segments(board.shape()[0] as isize, pattern.len(), |start, inc| {
+        push_segment_next_sq_matches(board, pattern, color, symmetric, start, inc, pairs);
+    });
+}
+
+/// Search for a (color specific) 1d pattern on a 2d board including the given point, returning next_sq match pairs.
+/// Same as search_point_next_sq.
+pub fn search_point_next_sq_into(board: &Array2<u8>, pattern: &[u8], color: u8, point: Point, pairs: &mut Vec<NSQMatch>) {
+    let symmetric = is_symmetric(pattern);
+    visit_point_segments(board.shape()[0] as isize, pattern.len(), point, |start, inc| {
+        push_segment_next_sq_matches(board, pattern, color, symmetric, start, inc, pairs);
+    });
+}
+
+/// Search for a (color specific) 1d pattern on a 2d board including the given point as an own_sq, returning next_sq match pairs.
+/// Same as search_point_own_next_sq.
+pub fn search_point_own_next_sq_into(board: &Array2<u8>, pattern: &[u8], color: u8, point: Point, own_sqs: &[isize], pairs: &mut Vec<NSQMatch>) {
+    if board[(point.0 as usize, point.1 as usize)] != color {
+        return;
+    }
+
+    let side = board.shape()[0] as isize;
+    let length = pattern.len();
+    let last = length as isize - 1;
+
+    for d in 0..NUM_AXES {
+        let inc = increments(d);
+        let start_at = |h: isize| (point.0 - inc.0 * h, point.1 - inc.1 * h);
+        let at = |start: Point, k: usize| (idx(start.0, inc.0, k), idx(start.1, inc.1, k));
+
+        for own_sq in own_sqs.iter() {
+            let start = start_at(*own_sq);
+            if segment_on_board(side, length, start, inc) {
+                if let Some(k) = next_sq_segment(board, pattern, color, start, inc, false) {
+                    pairs.push((at(start, k), (start, at(start, length - 1))));
+                }
+            }
+        }
+
+        for own_sq in own_sqs.iter() {
+            let h = last - own_sq;
+            let start = start_at(h);
+
+            if segment_on_board(side, length, start, inc) {
+                let fwd = if own_sqs.contains(&h) {
+                    next_sq_segment(board, pattern, color, start, inc, false)
+                } else {
+                    None
+                };
+
+                if let Some(k) = next_sq_segment(board, pattern, color, start, inc, true) {
+                    if fwd != Some(k) {
+                        pairs.push((at(start, k), (at(start, length - 1), start)));
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// Apply given pattern at given point in given direction.
 ///
 /// Returns True if application was succesful.
@@ -0,0 +1,251 @@
+//! Table driven pattern search.
+//!
+//! A window of WINDOW_LEN consecutive squares along a direction is encoded as a number, with 2 bits per square
+//! (EMPTY, OWN, ENEMY or WALL, relative to the color being searched for), and the first square in the lowest bits.
+//! Squares beyond the edge of the board are encoded as WALL.
+//! Precomputed tables map each code to the patterns (in either orientation) that match at the start of the window,
+//! and to the patterns that would match if a single (next_sq) square were filled by color.
+//! Since a pattern may be shorter than the window, and the squares beyond the board aren't actually WALL,
+//! every entry records the length of the pattern, so that the caller can check that the pattern fits on the board.
+
+use crate::consts::{EMPTY, ENEMY, MAX_PATTERN_LEN, OWN, STONE, WALL};
+use crate::geometry::{increments, Point};
+use crate::pattern::{Threat, ThreatPri, NUM_PTNS, PATTERNS, PATTERNS_BY_PRI};
+use crate::pattern_search::{dedupe_matches, dedupe_next_sq_match_pairs, Match, NSQMatch};
+use lazy_static::lazy_static;
+use ndarray::prelude::*;
+
+/// Number of squares in a window, i.e., the length of the longest pattern.
+pub const WINDOW_LEN: usize = MAX_PATTERN_LEN;
+
+/// Number of distinct window codes.
+pub const NUM_CODES: usize = 1 << (2 * WINDOW_LEN);
+
+/// Generic elements, from BLACK's POV, by code digit.
+const DIGIT_ELEMS: [u8; 4] = [EMPTY, OWN, ENEMY, WALL];
+
+/// Code digit of the element on the board, relative to color.
+#[inline(always)]
+fn elem_digit(elem: u8, color: u8) -> usize {
+    if elem == EMPTY {
+        0
+    } else if elem == color {
+        1
+    } else if elem & STONE != 0 {
+        2
+    } else {
+        3
+    }
+}
+
+/// A pattern found at the start of a window.
+///
+/// If reversed, then the pattern lies in the direction opposite to that of the window.
+/// next_sq is the offset of the next_sq from the start of the window, if any.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TableEntry {
+    pub pidx: usize,
+    pub reversed: bool,
+    pub length: usize,
+    pub next_sq: Option<usize>,
+}
+
+/// Entries for each code, stored contiguously: the entries for code are entries[offsets[code]..offsets[code + 1]].
+#[derive(Clone, Debug)]
+pub struct PatternTable {
+    pub offsets: Vec<u32>,
+    pub entries: Vec<TableEntry>,
+}
+
+impl PatternTable {
+    /// Build the table, given a function that returns the entry for a pattern (as oriented) on the squares of a window, if any.
+    fn build<F: Fn(&[u8], &[u8]) -> Option<Option<usize>>>(entry: F) -> Self {
+        for p in PATTERNS.iter() {
+            assert!(p.pattern.len() <= WINDOW_LEN);
+        }
+
+        let mut offsets: Vec<u32> = Vec::with_capacity(NUM_CODES + 1);
+        let mut entries: Vec<TableEntry> = Vec::new();
+
+        for code in 0..NUM_CODES {
+            offsets.push(entries.len() as u32);
+            let sqs = (0..WINDOW_LEN).map(|k| DIGIT_ELEMS[(code >> (2 * k)) & 3]).collect::<Vec<u8>>();
+
+            for p in PATTERNS.iter() {
+                for reversed in [false, true].iter() {
+                    let pattern = if *reversed {
+                        p.pattern.iter().rev().copied().collect::<Vec<u8>>()
+                    } else {
+                        p.pattern.to_owned()
+                    };
+
+                    if let Some(next_sq) = entry(&pattern, &sqs) {
+                        entries.push(TableEntry {
+                            pidx: p.index,
+                            reversed: *reversed,
+                            length: pattern.len(),
+                            next_sq,
+                        });
+                    }
+                }
+            }
+        }
+        offsets.push(entries.len() as u32);
+
+        Self { offsets, entries }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, code: usize) -> &[TableEntry] {
+        &self.entries[self.offsets[code] as usize..self.offsets[code + 1] as usize]
+    }
+}
+
+lazy_static! {
+    /// Patterns that match at the start of a window.
+    pub static ref MATCH_TABLE: PatternTable = PatternTable::build(|pattern, sqs| {
+        if pattern.iter().zip(sqs.iter()).all(|(p, s)| p & s != 0) {
+            Some(None)
+        } else {
+            None
+        }
+    });
+
+    /// Patterns that match at the start of a window if a single EMPTY square (the next_sq) is filled by OWN.
+    /// Same rules as pattern_search::search_point_next_sq.
+    pub static ref NEXT_SQ_TABLE: PatternTable = PatternTable::build(|pattern, sqs| {
+        let mut next_sq: Option<usize> = None;
+
+        for (k, (p, s)) in pattern.iter().zip(sqs.iter()).enumerate() {
+            if p & s == 0 {
+                if next_sq.is_none() && *p == OWN && *s == EMPTY {
+                    next_sq = Some(k);
+                } else {
+                    return None;
+                }
+            }
+        }
+
+        next_sq.map(Some)
+    });
+}
+
+/// Check if the point is on the board.
+#[inline(always)]
+fn on_board(board: &Array2<u8>, point: Point) -> bool {
+    let side = board.shape()[0] as isize;
+    point.0 >= 0 && point.1 >= 0 && point.0 < side && point.1 < side
+}
+
+/// Code digit at the given point, relative to color. WALL if it's not on the board.
+#[inline(always)]
+fn point_digit(board: &Array2<u8>, color: u8, point: Point) -> usize {
+    if on_board(board, point) {
+        elem_digit(board[(point.0 as usize, point.1 as usize)], color)
+    } else {
+        3
+    }
+}
+
+/// Code of the window starting at the given point, along direction d.
+pub fn window_code(board: &Array2<u8>, color: u8, start: Point, d: usize) -> usize {
+    let (row_inc, col_inc) = increments(d);
+    (0..WINDOW_LEN)
+        .map(|k| point_digit(board, color, (start.0 + row_inc * k as isize, start.1 + col_inc * k as isize)) << (2 * k))
+        .sum()
+}
+
+/// Visit the table entries of all the windows (along the first 4 directions) which start within WINDOW_LEN - 1 squares of point,
+/// and whose patterns fit on the board and include point.
+///
+/// The visitor gets the entry, the direction, and the start of the window.
+fn visit_point<F: FnMut(&TableEntry, usize, Point)>(board: &Array2<u8>, color: u8, point: Point, table: &PatternTable, mut visit: F) {
+    let w = WINDOW_LEN as isize;
+
+    for d in 0..4 {
+        let (row_inc, col_inc) = increments(d);
+        let at = |h: isize| (point.0 + row_inc * h, point.1 + col_inc * h);
+
+        // Roll the code along the line, one square at a time.
+        let mut code = window_code(board, color, at(-w), d);
+
+        for h in -(w - 1)..=0 {
+            code = (code >> 2) | (point_digit(board, color, at(h + w - 1)) << (2 * (WINDOW_LEN - 1)));
+            let start = at(h);
+
+            if !on_board(board, start) {
+                continue;
+            }
+
+            for entry in table.get(code) {
+                let length = entry.length as isize;
+                // The pattern must include point (at -h from its start), and its end must be on the board.
+                if length > -h && on_board(board, at(h + length - 1)) {
+                    visit(entry, d, start);
+                }
+            }
+        }
+    }
+}
+
+/// Match of a table entry with the window starting at start along direction d, oriented as the pattern.
+#[inline(always)]
+fn entry_match(entry: &TableEntry, d: usize, start: Point) -> Match {
+    let (row_inc, col_inc) = increments(d);
+    let length = entry.length as isize;
+    let end = (start.0 + row_inc * (length - 1), start.1 + col_inc * (length - 1));
+
+    if entry.reversed {
+        (end, start)
+    } else {
+        (start, end)
+    }
+}
+
+/// All pattern matches including the given point, by pattern index. Same as pattern_search::search_point, for every pattern.
+pub fn classify_point(board: &Array2<u8>, color: u8, point: Point) -> Vec<Vec<Match>> {
+    let mut matches: Vec<Vec<Match>> = vec![Vec::new(); *NUM_PTNS];
+
+    visit_point(board, color, point, &MATCH_TABLE, |entry, d, start| {
+        matches[entry.pidx].push(entry_match(entry, d, start));
+    });
+
+    for x in matches.iter_mut() {
+        dedupe_matches(x);
+    }
+
+    matches
+}
+
+/// All next_sqs and pattern matches including the given point, by pattern index.
+/// Same as pattern_search::search_point_next_sq, for every pattern.
+pub fn classify_point_next_sq(board: &Array2<u8>, color: u8, point: Point) -> Vec<Vec<NSQMatch>> {
+    let mut pairs: Vec<Vec<NSQMatch>> = vec![Vec::new(); *NUM_PTNS];
+
+    visit_point(board, color, point, &NEXT_SQ_TABLE, |entry, d, start| {
+        let (row_inc, col_inc) = increments(d);
+        let k = entry.next_sq.unwrap() as isize;
+        let next_sq = (start.0 + row_inc * k, start.1 + col_inc * k);
+        pairs[entry.pidx].push((next_sq, entry_match(entry, d, start)));
+    });
+
+    for x in pairs.iter_mut() {
+        dedupe_next_sq_match_pairs(x);
+    }
+
+    pairs
+}
+
+/// Get all pattern matches including the given point. Same as pattern::search_all_point.
+pub fn search_all_point_table(board: &Array2<u8>, color: u8, point: Point, pri: ThreatPri) -> Vec<Threat> {
+    let matches = classify_point(board, color, point);
+    let mut threats: Vec<Threat> = Vec::new();
+
+    for p in PATTERNS_BY_PRI[&pri] {
+        for m in matches[p.index].iter() {
+            threats.push(Threat::new(*m, p));
+        }
+    }
+
+    threats
+}
@@ -0,0 +1,437 @@
+//! Implements Proof-Number Search, using Threat Space Search to initialize the proof and disproof numbers.
+//!
+//! The attacker only plays moves that create threats (threes or better),
+//! and the defender only plays moves that block the attacker's most pressing threats
+//! or that make a four of their own. A position is decided only within this (threat based) move space.
+//! A win is only proven when the attacker completes five, or when the defender can't stop a four.
+//! TSS (which may find wins that don't exist) is only used to decide which nodes to expand first.
+//!
+//! The board is kept both as an array (for TSS and the next_sq searches) and as a FixedBoard, which is used to search for the threats
+//! (with search_all_board_simd).
+
+use crate::board::{clear_sq, set_sq};
+use crate::consts::{MAX_DEFCON, MDFIT, STONE};
+use crate::fixed_board::{to_sq, FixedBoard};
+use crate::geometry::Point;
+use crate::pattern::{Threat, ThreatPri, PATTERNS, P_WIN};
+use crate::pattern_search::{search_board_next_sq_into, Match, NSQMatch};
+use crate::simd_search::search_all_board_simd;
+use crate::threat_space_search::{tss_board_limited, TSSLimits, TSSMode};
+use fnv::FnvHashSet;
+use ndarray::prelude::*;
+use std::time::Instant;
+
+/// Proof and disproof numbers of a solved node are 0 or PN_INF.
+pub const PN_INF: u32 = u32::MAX;
+
+/// Initial disproof (proof) number of an attacker's node where TSS finds (doesn't find) a potential win.
+pub const TSS_HINT: u32 = 4;
+
+/// Node limit of the TSS run for each hint.
+pub const TSS_HINT_MAX_NODES: usize = 1000;
+
+/// Outcome of a Proof-Number Search, from the POV of the side to move.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PNOutcome {
+    Win,
+    Loss,
+    Unknown,
+}
+
+/// Limits on a Proof-Number Search.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PNLimits {
+    pub max_nodes: Option<usize>,
+    pub deadline: Option<Instant>,
+}
+
+impl PNLimits {
+    pub fn new(max_nodes: Option<usize>, deadline: Option<Instant>) -> Self {
+        Self { max_nodes, deadline }
+    }
+
+    /// True if the search has to stop after creating num_nodes nodes.
+    pub fn reached(&self, num_nodes: usize) -> bool {
+        self.max_nodes.is_some_and(|x| num_nodes >= x) || self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+}
+
+/// Result of a Proof-Number Search.
+#[derive(Clone, Debug)]
+pub struct PNResult {
+    pub outcome: PNOutcome,
+    pub best_move: Option<Point>,
+    pub num_nodes: usize,
+}
+
+/// A node in the Proof-Number Search tree.
+/// At an OR node the attacker is to move, and at an AND node the defender is to move.
+#[derive(Clone, Debug)]
+pub struct PNNode {
+    pub sq: Option<Point>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub and_node: bool,
+    pub pn: u32,
+    pub dn: u32,
+    pub expanded: bool,
+}
+
+impl PNNode {
+    /// potential_win is the verdict of TSS on an unsolved node, if any.
+    pub fn new(sq: Option<Point>, parent: Option<usize>, and_node: bool, value: Option<bool>, potential_win: Option<bool>) -> Self {
+        let (pn, dn) = match (value, potential_win) {
+            (Some(true), _) => (0, PN_INF),
+            (Some(false), _) => (PN_INF, 0),
+            (None, Some(true)) => (1, TSS_HINT),
+            (None, Some(false)) => (TSS_HINT, 1),
+            (None, None) => (1, 1),
+        };
+
+        Self {
+            sq,
+            parent,
+            children: Vec::new(),
+            and_node,
+            pn,
+            dn,
+            expanded: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn solved(&self) -> bool {
+        self.pn == 0 || self.dn == 0
+    }
+}
+
+/// Immediate threats of the given color with the min defcon, along with that defcon.
+fn min_defcon_threats(board: &FixedBoard, color: u8) -> (usize, Vec<Threat>) {
+    let threats = search_all_board_simd(board, color, ThreatPri::Immediate);
+    let min_defcon = threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
+    let threats = threats.into_iter().filter(|x| x.defcon == min_defcon).collect::<Vec<Threat>>();
+    (min_defcon, threats)
+}
+
+/// Intersection of critical_sqs of the given threats.
+fn common_critical_sqs(threats: &[Threat]) -> FnvHashSet<Point> {
+    threats
+        .iter()
+        .map(|x| x.critical_sqs.to_owned())
+        .reduce(|a, b| a.intersection(&b).copied().collect::<FnvHashSet<Point>>())
+        .unwrap_or_default()
+}
+
+/// All squares where the given color can create an Immediate pattern of defcon <= max_defcon.
+fn next_sqs_max_defcon(board: &Array2<u8>, color: u8, max_defcon: usize) -> FnvHashSet<Point> {
+    let mut pairs: Vec<NSQMatch> = Vec::new();
+
+    for p in PATTERNS.iter().filter(|x| x.immediate && x.defcon <= max_defcon) {
+        search_board_next_sq_into(board, p.for_color(color), color, &mut pairs);
+    }
+
+    pairs.into_iter().map(|x| x.0).collect()
+}
+
+/// True if color has won by playing at point.
+fn won_at(board: &FixedBoard, color: u8, point: Point) -> bool {
+    let mut matches: Vec<Match> = Vec::new();
+    board.search_point_into(P_WIN.for_color(color), to_sq(point), &mut matches);
+    !matches.is_empty()
+}
+
+/// Static value of a node (Some(true) if proven, Some(false) if disproven, None if unknown).
+///
+/// A node is only proven statically if the defender is to move and can't stop a four (and has none of their own).
+/// The attacker's fours are left to the search, so that the winning move (the five) is part of the tree.
+fn evaluate(board: &FixedBoard, attacker: u8, and_node: bool) -> Option<bool> {
+    let defender = attacker ^ STONE;
+    let (min_defcon, threats) = min_defcon_threats(board, attacker);
+    let (opp_min_defcon, opp_threats) = min_defcon_threats(board, defender);
+
+    if and_node {
+        if opp_min_defcon <= 1 {
+            // Defender makes five.
+            Some(false)
+        } else if min_defcon > MDFIT {
+            // The attacker's last move wasn't forcing, so the defender's moves can't be restricted.
+            Some(false)
+        } else if min_defcon <= 1 && common_critical_sqs(&threats).is_empty() {
+            // The defender can't stop all the fours.
+            Some(true)
+        } else {
+            None
+        }
+    } else if min_defcon > 1 && opp_min_defcon <= 1 && common_critical_sqs(&opp_threats).is_empty() {
+        // Attacker can't stop all the defender's fours.
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Verdict of TSS on an unsolved attacker's node (None at the defender's nodes). Used to initialize the proof and disproof numbers.
+///
+/// TSS is limited to TSS_HINT_MAX_NODES nodes (and the deadline of the search).
+/// A run that is cut off before it finds a potential win reads as "no win".
+fn tss_hint(board: &mut Array2<u8>, attacker: u8, and_node: bool, tss_limits: &TSSLimits) -> Option<bool> {
+    if and_node {
+        None
+    } else {
+        Some(tss_board_limited(board, attacker, TSSMode::VCT, tss_limits).tree.root().potential_win)
+    }
+}
+
+/// Initial proof and disproof numbers of a node, given by its static value, or by TSS if it's unsolved.
+fn new_node(
+    board: &mut Array2<u8>,
+    fixed_board: &FixedBoard,
+    attacker: u8,
+    sq: Option<Point>,
+    parent: Option<usize>,
+    and_node: bool,
+    tss_limits: &TSSLimits,
+) -> PNNode {
+    let value = evaluate(fixed_board, attacker, and_node);
+    let potential_win = if value.is_none() {
+        tss_hint(board, attacker, and_node, tss_limits)
+    } else {
+        None
+    };
+
+    PNNode::new(sq, parent, and_node, value, potential_win)
+}
+
+/// Generate moves for the side to move.
+fn gen_moves(board: &Array2<u8>, fixed_board: &FixedBoard, attacker: u8, and_node: bool) -> Vec<Point> {
+    let defender = attacker ^ STONE;
+    let mut moves: FnvHashSet<Point> = FnvHashSet::default();
+
+    if and_node {
+        let (min_defcon, threats) = min_defcon_threats(fixed_board, attacker);
+
+        if min_defcon <= 1 {
+            // Must stop all the fours at once.
+            moves.extend(common_critical_sqs(&threats));
+        } else if min_defcon < MAX_DEFCON {
+            // Block any of the threes, or counter with a four.
+            for t in threats.iter() {
+                moves.extend(t.critical_sqs.iter());
+            }
+            moves.extend(next_sqs_max_defcon(board, defender, 1));
+        }
+    } else {
+        let (min_defcon, _) = min_defcon_threats(fixed_board, attacker);
+        let (opp_min_defcon, opp_threats) = min_defcon_threats(fixed_board, defender);
+
+        if min_defcon <= 1 {
+            // Make five.
+            let mut pairs: Vec<NSQMatch> = Vec::new();
+            search_board_next_sq_into(board, P_WIN.for_color(attacker), attacker, &mut pairs);
+            moves.extend(pairs.into_iter().map(|x| x.0));
+        } else if opp_min_defcon <= 1 {
+            moves.extend(common_critical_sqs(&opp_threats));
+        } else {
+            moves.extend(next_sqs_max_defcon(board, attacker, MDFIT));
+        }
+    }
+
+    let mut moves = moves.into_iter().collect::<Vec<Point>>();
+    moves.sort_unstable();
+    moves
+}
+
+/// Proof-Number Search tree along with the root board and the attacker.
+/// The TSS hints share the deadline of the Proof-Number Search, and each is limited to TSS_HINT_MAX_NODES nodes.
+pub struct PNTree {
+    pub board: Array2<u8>,
+    fixed_board: FixedBoard,
+    pub attacker: u8,
+    pub nodes: Vec<PNNode>,
+    pub tss_limits: TSSLimits,
+}
+
+impl PNTree {
+    pub fn new(board: &Array2<u8>, attacker: u8, root_and_node: bool, limits: &PNLimits) -> Self {
+        let mut board = board.to_owned();
+        let fixed_board = FixedBoard::from_array(&board);
+        let tss_limits = TSSLimits {
+            max_nodes: Some(TSS_HINT_MAX_NODES),
+            deadline: limits.deadline,
+            ..TSSLimits::default()
+        };
+        let nodes = Vec::from([new_node(&mut board, &fixed_board, attacker, None, None, root_and_node, &tss_limits)]);
+
+        Self {
+            board,
+            fixed_board,
+            attacker,
+            nodes,
+            tss_limits,
+        }
+    }
+
+    /// Color to move at the given node.
+    #[inline(always)]
+    fn to_move(&self, node: usize) -> u8 {
+        if self.nodes[node].and_node {
+            self.attacker ^ STONE
+        } else {
+            self.attacker
+        }
+    }
+
+    /// Play a move on both boards.
+    fn set_sq(&mut self, color: u8, sq: Point) {
+        set_sq(&mut self.board, color, sq);
+        self.fixed_board.set_sq(color, to_sq(sq));
+    }
+
+    /// Take back a move on both boards.
+    fn clear_sq(&mut self, color: u8, sq: Point) {
+        clear_sq(&mut self.board, color, sq);
+        self.fixed_board.clear_sq(color, to_sq(sq));
+    }
+
+    /// Descend to the most proving node, playing out the moves on the board.
+    /// Returns the most proving node and the path of moves played.
+    fn select_most_proving(&mut self) -> (usize, Vec<(u8, Point)>) {
+        let mut node = 0;
+        let mut path: Vec<(u8, Point)> = Vec::new();
+
+        while self.nodes[node].expanded {
+            let n = &self.nodes[node];
+            let next = if n.and_node {
+                n.children.iter().copied().min_by_key(|&x| self.nodes[x].dn)
+            } else {
+                n.children.iter().copied().min_by_key(|&x| self.nodes[x].pn)
+            }
+            .unwrap();
+
+            let color = self.to_move(node);
+            let sq = self.nodes[next].sq.unwrap();
+            self.set_sq(color, sq);
+            path.push((color, sq));
+            node = next;
+        }
+
+        (node, path)
+    }
+
+    /// Expand the given node, evaluating all of its children.
+    fn expand(&mut self, node: usize) {
+        let and_node = self.nodes[node].and_node;
+        let color = self.to_move(node);
+
+        for sq in gen_moves(&self.board, &self.fixed_board, self.attacker, and_node) {
+            self.set_sq(color, sq);
+
+            // Attacker is to move at the child node iff the defender is to move at the current node.
+            let child_node = if won_at(&self.fixed_board, color, sq) {
+                PNNode::new(Some(sq), Some(node), !and_node, Some(!and_node), None)
+            } else {
+                new_node(
+                    &mut self.board,
+                    &self.fixed_board,
+                    self.attacker,
+                    Some(sq),
+                    Some(node),
+                    !and_node,
+                    &self.tss_limits,
+                )
+            };
+
+            self.clear_sq(color, sq);
+
+            let child = self.nodes.len();
+            self.nodes.push(child_node);
+            self.nodes[node].children.push(child);
+        }
+
+        self.nodes[node].expanded = true;
+    }
+
+    /// Recompute proof and disproof numbers from the given node up to the root.
+    fn update_ancestors(&mut self, node: usize) {
+        let mut curr = Some(node);
+
+        while let Some(n) = curr {
+            if self.nodes[n].expanded {
+                let children = &self.nodes[n].children;
+                let min_pn = children.iter().map(|&x| self.nodes[x].pn).min().unwrap_or(PN_INF);
+                let min_dn = children.iter().map(|&x| self.nodes[x].dn).min().unwrap_or(PN_INF);
+                let sum_pn = children.iter().fold(0, |a: u32, &x| a.saturating_add(self.nodes[x].pn));
+                let sum_dn = children.iter().fold(0, |a: u32, &x| a.saturating_add(self.nodes[x].dn));
+
+                // A node without children is lost for the side to move.
+                let (pn, dn) = match (self.nodes[n].and_node, children.is_empty()) {
+                    (true, true) => (0, PN_INF),
+                    (false, true) => (PN_INF, 0),
+                    (true, false) => (sum_pn, min_dn),
+                    (false, false) => (min_pn, sum_dn),
+                };
+
+                self.nodes[n].pn = pn;
+                self.nodes[n].dn = dn;
+            }
+
+            curr = self.nodes[n].parent;
+        }
+    }
+
+    /// Run the search until the root is solved or the limits are reached.
+    pub fn search(&mut self, limits: &PNLimits) {
+        while !self.nodes[0].solved() && !limits.reached(self.nodes.len()) {
+            let (node, path) = self.select_most_proving();
+            self.expand(node);
+            self.update_ancestors(node);
+
+            for (color, sq) in path.iter().rev() {
+                self.clear_sq(*color, *sq);
+            }
+        }
+    }
+
+    /// A proven move at the root, if any.
+    pub fn proven_move(&self) -> Option<Point> {
+        self.nodes[0]
+            .children
+            .iter()
+            .find(|&&x| self.nodes[x].pn == 0)
+            .and_then(|&x| self.nodes[x].sq)
+    }
+}
+
+/// Solve the position for the side to move (color) within the given limits.
+///
+/// First tries to prove a win for color. If that fails, tries to prove a win for the opponent.
+/// Both searches share the limits.
+pub fn pn_solve(board: &Array2<u8>, color: u8, limits: &PNLimits) -> PNResult {
+    let mut win_tree = PNTree::new(board, color, false, limits);
+    win_tree.search(limits);
+
+    // The attacker's root is never proven statically, so a proven root has a proven child.
+    if win_tree.nodes[0].pn == 0 {
+        return PNResult {
+            outcome: PNOutcome::Win,
+            best_move: win_tree.proven_move(),
+            num_nodes: win_tree.nodes.len(),
+        };
+    }
+
+    let remaining_limits = PNLimits::new(limits.max_nodes.map(|x| x.saturating_sub(win_tree.nodes.len())), limits.deadline);
+    let mut loss_tree = PNTree::new(board, color ^ STONE, true, &remaining_limits);
+    loss_tree.search(&remaining_limits);
+
+    let outcome = if loss_tree.nodes[0].pn == 0 {
+        PNOutcome::Loss
+    } else {
+        PNOutcome::Unknown
+    };
+
+    PNResult {
+        outcome,
+        best_move: None,
+        num_nodes: win_tree.nodes.len() + loss_tree.nodes.len(),
+    }
+}
@@ -0,0 +1,173 @@
+//! Board wide pattern search, testing a pattern at many starting squares at once.
+//!
+//! The squares of a FixedBoard are laid out in a padded buffer, so that the squares at a constant offset
+//! from LANES consecutive starting squares can be loaded as a single vector. For each element of the pattern,
+//! the vector is ANDed with the element, and the lanes that are still non-zero at the end are the matches,
+//! subject to the pattern fitting on the board from that starting square (see fixed_board::STEPS).
+//! Lines are scanned along the first NUM_AXES directions only (whose offsets are all positive),
+//! testing the pattern in both orientations, as in pattern_search::search_board_into.
+//!
+//! On x86_64, SSE2 (which is always available there) is used. Elsewhere, the lanes are tested one by one.
+
+use crate::consts::{NUM_AXES, SIDE_LEN};
+use crate::fixed_board::{step, to_point, FixedBoard, Sq, DIR_OFFSETS, NUM_SQS, STEPS};
+use crate::pattern::{Threat, ThreatPri, PATTERNS_BY_PRI};
+use crate::pattern_search::Match;
+
+/// Number of starting squares tested at once.
+pub const LANES: usize = 16;
+
+/// Length of the padded buffer: the farthest square read is at offset (SIDE_LEN - 1) * (SIDE_LEN + 1)
+/// from a starting square, for the longest possible pattern along the longest offset.
+const BUF_LEN: usize = NUM_SQS.div_ceil(LANES) * LANES + (SIDE_LEN - 1) * (SIDE_LEN + 1) + LANES;
+
+// The longest offset among the first NUM_AXES directions is SIDE_LEN + 1, and all of them are positive.
+const _: () = {
+    let mut d = 0;
+    while d < NUM_AXES {
+        assert!(DIR_OFFSETS[d] > 0 && DIR_OFFSETS[d] <= SIDE_LEN as isize + 1);
+        d += 1;
+    }
+};
+
+/// Squares of the board, followed by zeros (which don't match any pattern element).
+fn padded_sqs(board: &FixedBoard) -> [u8; BUF_LEN] {
+    let mut buf = [0; BUF_LEN];
+    buf[..NUM_SQS].copy_from_slice(board.sqs());
+    buf
+}
+
+/// Bitmask of the lanes (starting squares base..(base + LANES)) at which the (color specific) pattern matches,
+/// stepping by offset, reading the pattern backwards if reversed. One lane at a time.
+#[inline(always)]
+pub fn lane_matches_scalar(buf: &[u8], base: usize, offset: usize, pattern: &[u8], reversed: bool) -> u32 {
+    let length = pattern.len();
+    let mut lanes: u32 = (1 << LANES) - 1;
+
+    for k in 0..length {
+        let elem = if reversed { pattern[length - 1 - k] } else { pattern[k] };
+        let window = &buf[(base + k * offset)..(base + k * offset + LANES)];
+
+        for (lane, x) in window.iter().enumerate() {
+            if elem & x == 0 {
+                lanes &= !(1 << lane);
+            }
+        }
+
+        if lanes == 0 {
+            break;
+        }
+    }
+
+    lanes
+}
+
+/// Same as lane_matches_scalar, using SSE2.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub fn lane_matches_sse2(buf: &[u8], base: usize, offset: usize, pattern: &[u8], reversed: bool) -> u32 {
+    use std::arch::x86_64::{__m128i, _mm_and_si128, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8, _mm_setzero_si128};
+
+    let length = pattern.len();
+    assert!(base + (length - 1) * offset + LANES <= buf.len());
+
+    let mut lanes: u32 = (1 << LANES) - 1;
+
+    // SAFETY: SSE2 is part of the x86_64 baseline, and every load is within buf, as asserted above.
+    unsafe {
+        let zero = _mm_setzero_si128();
+
+        for k in 0..length {
+            let elem = if reversed { pattern[length - 1 - k] } else { pattern[k] };
+            let window = _mm_loadu_si128(buf.as_ptr().add(base + k * offset) as *const __m128i);
+            let misses = _mm_cmpeq_epi8(_mm_and_si128(window, _mm_set1_epi8(elem as i8)), zero);
+            lanes &= !(_mm_movemask_epi8(misses) as u32);
+
+            if lanes == 0 {
+                break;
+            }
+        }
+    }
+
+    lanes
+}
+
+/// Bitmask of the lanes at which the pattern matches. See lane_matches_scalar.
+#[inline(always)]
+fn lane_matches(buf: &[u8], base: usize, offset: usize, pattern: &[u8], reversed: bool) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        lane_matches_sse2(buf, base, offset, pattern, reversed)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        lane_matches_scalar(buf, base, offset, pattern, reversed)
+    }
+}
+
+/// Search for a (color specific) 1d pattern on the (padded) board, with the given function to test the lanes.
+#[inline(always)]
+fn search_buf_with<F: Fn(&[u8], usize, usize, &[u8], bool) -> u32>(buf: &[u8; BUF_LEN], pattern: &[u8], matches: &mut Vec<Match>, lane_fn: F) {
+    let length = pattern.len();
+    let symmetric = pattern.iter().eq(pattern.iter().rev());
+
+    for base in (0..NUM_SQS).step_by(LANES) {
+        // Lanes beyond the last square.
+        let valid: u32 = if base + LANES > NUM_SQS {
+            (1 << (NUM_SQS - base)) - 1
+        } else {
+            (1 << LANES) - 1
+        };
+
+        for d in 0..NUM_AXES {
+            let offset = DIR_OFFSETS[d] as usize;
+            let fwd = lane_fn(buf, base, offset, pattern, false) & valid;
+            // A segment that matches in both orientations is only recorded in the forward orientation.
+            let bwd = if symmetric {
+                0
+            } else {
+                lane_fn(buf, base, offset, pattern, true) & valid & !fwd
+            };
+
+            for (lanes, reversed) in [(fwd, false), (bwd, true)] {
+                let mut lanes = lanes;
+                while lanes != 0 {
+                    let sq = (base + lanes.trailing_zeros() as usize) as Sq;
+                    lanes &= lanes - 1;
+
+                    // The pattern must fit on the board, without wrapping around the edge.
+                    if STEPS[sq as usize][d] as usize + 1 >= length {
+                        let (start, end) = (to_point(sq), to_point(step(sq, d, length as isize - 1)));
+                        matches.push(if reversed { (end, start) } else { (start, end) });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Search for a (color specific) 1d pattern on the board. Same as pattern_search::search_board_into.
+pub fn search_board_simd_into(board: &FixedBoard, pattern: &[u8], matches: &mut Vec<Match>) {
+    search_buf_with(&padded_sqs(board), pattern, matches, lane_matches);
+}
+
+/// Same as search_board_simd_into, testing the lanes one at a time.
+pub fn search_board_scalar_into(board: &FixedBoard, pattern: &[u8], matches: &mut Vec<Match>) {
+    search_buf_with(&padded_sqs(board), pattern, matches, lane_matches_scalar);
+}
+
+/// Get all pattern matches on the board. Same as pattern::search_all_board.
+pub fn search_all_board_simd(board: &FixedBoard, color: u8, pri: ThreatPri) -> Vec<Threat> {
+    let mut threats: Vec<Threat> = Vec::new();
+    let buf = padded_sqs(board);
+
+    let mut matches: Vec<Match> = Vec::new();
+    for p in PATTERNS_BY_PRI[&pri] {
+        matches.clear();
+        search_buf_with(&buf, p.for_color(color), &mut matches, lane_matches);
+        threats.extend(matches.iter().map(|m| Threat::new(*m, p)));
+    }
+
+    threats
+}
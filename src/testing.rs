@@ -1,15 +1,41 @@
 //! Regression tests for this project.
 
-use crate::board::new_board;
-use crate::consts::{COLORS, EMPTY, NUM_DIRECTIONS, SIDE_LEN, WIN_LENGTH};
+use crate::bitboard::BitBoard;
+use crate::board::{algebraic_to_point, get_board, new_board, point_to_algebraic};
+use crate::board::{clear_sq, set_sq};
+use crate::consts::STONE;
+use crate::consts::{BLACK, COLORS, EMPTY, ENEMY, MAX_DEFCON, NUM_AXES, NUM_DIRECTIONS, OWN, SIDE_LEN, WALL, WHITE, WIN_LENGTH};
+use crate::defence::defence_sqs;
+use crate::engine::{candidate_moves, engine_search, EngineLimits};
+use crate::evaluation::{evaluate, is_mate_score, loss_in, mate_plies, win_in, MAX_EVAL, TEMPO_BONUS};
+use crate::fixed_board::{step, to_point, to_sq, FixedBoard, Sq, DIR_OFFSETS, NUM_SQS, STEPS};
 use crate::geometry::Point;
 use crate::geometry::{increments, point_is_on_line, point_on_line};
-use crate::pattern::PATTERNS;
+use crate::move_analysis::{classify_move, find_fork, fork_moves, fork_threats, ForkKind, MoveEffect};
+use crate::pattern::{
+    search_all_board, search_all_point, search_all_point_own, search_all_point_set_own, Pattern, PatternSet, Threat, ThreatPri, PATTERNS, P_3_A,
+    P_3_B, P_3_C, P_4_A,
+};
+use crate::pattern_analysis::{check_completeness, critical_sqs_mismatches, Finding};
 use crate::pattern_search::{
-    apply_pattern, get_pattern, idx, matches_are_equal, next_sq_matches_are_subset, search_board, search_board_next_sq, search_point,
-    search_point_next_sq, search_point_own, search_point_own_next_sq, Match, NSQMatch,
+    apply_pattern, get_pattern, idx, line_at_point, matches_are_equal, next_sq_matches_are_equal, next_sq_matches_are_subset, search_board,
+    search_board_into, search_board_next_sq, search_board_next_sq_into, search_line_at, search_point, search_point_axis, search_point_into,
+    search_point_next_sq, search_point_next_sq_into, search_point_own, search_point_own_into, search_point_own_next_sq,
+    search_point_own_next_sq_into, Match, NSQMatch,
+};
+use crate::pattern_table::{classify_point, classify_point_next_sq, search_all_point_table};
+use crate::pn_search::{pn_solve, PNLimits, PNOutcome};
+use crate::simd_search::{search_all_board_simd, search_board_scalar_into, search_board_simd_into};
+use crate::state::{get_state, State};
+use crate::threat_space_search::{
+    potential_win_variations, reduce_variations, tss_board, tss_board_iterative, tss_board_limited, variation_to_algebraic, SearchTree, TSSCutoff,
+    TSSLimits, TSSMode,
 };
+use crate::threat_tracker::ThreatTracker;
+use fnv::FnvHashSet;
 use ndarray::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 pub fn subtest_search_board(board: &Array2<u8>, gen_pattern: &[u8], color: u8, start: Point, end: Point) {
@@ -213,3 +239,1154 @@ pub fn test_pattern_search_fns() {
 pub fn test_pattern_search_fns_test() {
     test_pattern_search_fns();
 }
+
+/// Test Proof-Number Search on positions with known outcomes.
+pub fn test_pn_search() {
+    let limits = PNLimits::new(Some(10000), None);
+
+    // BLACK has an open three.
+    let s = get_state(&["g8", "h8", "i8", "a1"], &["g9", "h10", "a15", "o1"], BLACK, true);
+    let result = pn_solve(&s.board, s.turn, &limits);
+    assert_eq!(result.outcome, PNOutcome::Win);
+    assert!(result.best_move.is_some());
+
+    // WHITE has a straight four.
+    let s = get_state(&["a1", "a15", "o1", "h12"], &["d4", "e4", "f4", "g4"], BLACK, true);
+    assert_eq!(pn_solve(&s.board, s.turn, &limits).outcome, PNOutcome::Loss);
+
+    // 6. Xokk (B) vs. Victoria (W)
+    let s = get_state(
+        &["h8", "g7", "f8", "e9", "d10", "g6", "g9", "j8", "g8", "d8", "f10"],
+        &["h7", "f6", "i8", "h6", "c11", "g5", "i7", "f5", "g10", "e8"],
+        WHITE,
+        true,
+    );
+    let result = pn_solve(&s.board, s.turn, &limits);
+    assert_eq!(result.outcome, PNOutcome::Win);
+    let best_move = result.best_move.unwrap();
+    assert_eq!(s.board[(best_move.0 as usize, best_move.1 as usize)], EMPTY);
+
+    // TSS finds a win for BLACK here, because it doesn't consider the threats that WHITE can make.
+    // At most one of the sides can be proven to win, whoever is to move.
+    let blacks = ["g9", "h9", "j10", "k11", "a6", "o6"];
+    let whites = ["b5", "c4", "d3", "d2", "c2", "n5", "m4", "l3", "l2", "m2"];
+    let black_to_move = pn_solve(&get_state(&blacks, &whites, BLACK, false).board, BLACK, &limits);
+    let white_to_move = pn_solve(&get_state(&blacks, &whites, WHITE, false).board, WHITE, &limits);
+    assert_ne!(black_to_move.outcome, PNOutcome::Win);
+    assert_eq!(white_to_move.outcome, PNOutcome::Win);
+
+    // Nothing is happening yet.
+    let s = get_state(&["h8"], &["g7"], BLACK, true);
+    assert_eq!(pn_solve(&s.board, s.turn, &limits).outcome, PNOutcome::Unknown);
+}
+
+#[test]
+pub fn test_pn_search_test() {
+    test_pn_search();
+}
+
+/// Test that Threat Space Search honours its limits, and reports whether it was cut off.
+pub fn test_tss_limits() {
+    // 6. Xokk (B) vs. Victoria (W)
+    let mut s = get_state(
+        &["h8", "g7", "f8", "e9", "d10", "g6", "g9", "j8", "g8", "d8", "f10"],
+        &["h7", "f6", "i8", "h6", "c11", "g5", "i7", "f5", "g10", "e8"],
+        WHITE,
+        true,
+    );
+
+    let result = tss_board_limited(&mut s.board, s.turn, TSSMode::VCT, &TSSLimits::default());
+    assert!(result.finished);
+    assert!(result.tree.root().potential_win);
+    let num_nodes = result.num_nodes;
+
+    // Every node is stored in the tree, in depth-first pre-order.
+    assert_eq!(result.tree.len(), num_nodes + 1);
+    for (idx, _) in result.tree.iter() {
+        assert!(result.tree.children(idx).all(|x| x > idx));
+    }
+
+    // Every node except the root is counted towards the node limit.
+    assert_eq!(result.stats.nodes_per_depth.iter().sum::<usize>(), num_nodes + 1);
+    assert!(result.stats.max_branching_factor >= result.tree.children(SearchTree::ROOT).count());
+    assert!(result.stats.search_time <= result.stats.node_time);
+
+    let limits = TSSLimits::new(None, Some(num_nodes / 2), None, Arc::new(AtomicBool::new(false)));
+    let result = tss_board_limited(&mut s.board, s.turn, TSSMode::VCT, &limits);
+    assert!(!result.finished);
+    assert!(result.num_nodes <= num_nodes);
+
+    let limits = TSSLimits::new(Some(1), None, None, Arc::new(AtomicBool::new(false)));
+    let result = tss_board_limited(&mut s.board, s.turn, TSSMode::VCT, &limits);
+    assert!(!result.finished);
+    let tree = &result.tree;
+    assert!(tree.children(SearchTree::ROOT).all(|x| tree.children(x).count() == 0));
+    assert!(tree
+        .children(SearchTree::ROOT)
+        .any(|x| matches!(tree.node(x).cutoff, Some(TSSCutoff::MaxDepth))));
+
+    let limits = TSSLimits::default();
+    limits.cancel.store(true, Ordering::Relaxed);
+    let result = tss_board_limited(&mut s.board, s.turn, TSSMode::VCT, &limits);
+    assert!(!result.finished);
+    let tree = &result.tree;
+    assert!(!tree.root().potential_win);
+    assert!(tree
+        .children(SearchTree::ROOT)
+        .all(|x| matches!(tree.node(x).cutoff, Some(TSSCutoff::Stopped))));
+}
+
+#[test]
+pub fn test_tss_limits_test() {
+    test_tss_limits();
+}
+
+/// Test that iterative deepening Threat Space Search finds exactly the shortest potentially winning variations.
+pub fn test_tss_iterative() {
+    let states = [
+        // 4. Neuron (B) vs. Victoria (W)
+        get_state(
+            &["h8", "g7", "i9", "i8", "f9", "i11", "h11", "h9", "j5"],
+            &["h7", "f6", "g8", "i6", "g9", "i10", "j11", "k4", "j7"],
+            BLACK,
+            true,
+        ),
+        // 6. Xokk (B) vs. Victoria (W)
+        get_state(
+            &["h8", "g7", "f8", "e9", "d10", "g6", "g9", "j8", "g8", "d8", "f10"],
+            &["h7", "f6", "i8", "h6", "c11", "g5", "i7", "f5", "g10", "e8"],
+            WHITE,
+            true,
+        ),
+    ];
+
+    for s in states.iter() {
+        let mut board = s.board.to_owned();
+        let all_vars = potential_win_variations(&tss_board(&mut board, s.turn));
+        let min_len = all_vars[0].len();
+        let expected = all_vars
+            .iter()
+            .filter(|x| x.len() == min_len)
+            .map(|x| variation_to_algebraic(x).into_iter().map(|y| y.0).collect::<Vec<String>>())
+            .collect::<Vec<Vec<String>>>();
+
+        let result = tss_board_iterative(&mut board, s.turn, TSSMode::VCT, &TSSLimits::default(), true);
+        assert!(result.finished);
+        assert_eq!(result.depth, min_len);
+        assert_eq!(result.variations.len(), expected.len());
+        for v in result.variations.iter() {
+            assert!(expected.contains(&variation_to_algebraic(v).into_iter().map(|y| y.0).collect::<Vec<String>>()));
+        }
+
+        let result = tss_board_iterative(&mut board, s.turn, TSSMode::VCT, &TSSLimits::default(), false);
+        assert_eq!(result.variations.len(), 1);
+        assert_eq!(result.variations[0].len(), min_len);
+    }
+}
+
+#[test]
+pub fn test_tss_iterative_test() {
+    test_tss_iterative();
+}
+
+/// Test that every attacking move in a VCF variation makes a four, and that a VCF win is also a VCT win.
+pub fn test_tss_vcf() {
+    let states = [
+        // 1. Victoria (B) vs. Neuron (W)
+        get_state(
+            &["h8", "i7", "g9", "j6", "h6", "g6", "g8", "e8", "f7", "e6", "e5", "d6", "f4", "g4"],
+            &["i9", "h7", "f10", "k5", "j8", "i6", "g7", "f8", "d9", "d5", "e7", "f6", "g3", "i4"],
+            BLACK,
+            true,
+        ),
+        // 4. Neuron (B) vs. Victoria (W)
+        get_state(
+            &["h8", "g7", "i9", "i8", "f9", "i11", "h11", "h9", "j5"],
+            &["h7", "f6", "g8", "i6", "g9", "i10", "j11", "k4", "j7"],
+            BLACK,
+            true,
+        ),
+        // 6. Xokk (B) vs. Victoria (W)
+        get_state(
+            &["h8", "g7", "f8", "e9", "d10", "g6", "g9", "j8", "g8", "d8", "f10"],
+            &["h7", "f6", "i8", "h6", "c11", "g5", "i7", "f5", "g10", "e8"],
+            WHITE,
+            true,
+        ),
+    ];
+
+    let mut num_vcf_wins: usize = 0;
+
+    for s in states.iter() {
+        let mut board = s.board.to_owned();
+        let vcf = tss_board_limited(&mut board, s.turn, TSSMode::VCF, &TSSLimits::default());
+        let vct = tss_board_limited(&mut board, s.turn, TSSMode::VCT, &TSSLimits::default());
+        assert!(vcf.finished && vct.finished);
+        assert!(!vcf.tree.root().potential_win || vct.tree.root().potential_win);
+        assert!(vcf.num_nodes <= vct.num_nodes);
+
+        if vcf.tree.root().potential_win {
+            num_vcf_wins += 1;
+        }
+
+        for v in potential_win_variations(&vcf.tree).iter() {
+            for (sq, csqs) in v.iter() {
+                set_sq(&mut board, s.turn, *sq);
+                assert!(!search_all_point_own(&board, s.turn, *sq, ThreatPri::Four).is_empty());
+                for csq in csqs.iter() {
+                    set_sq(&mut board, s.turn ^ STONE, *csq);
+                }
+            }
+
+            for (sq, csqs) in v.iter() {
+                clear_sq(&mut board, s.turn, *sq);
+                for csq in csqs.iter() {
+                    clear_sq(&mut board, s.turn ^ STONE, *csq);
+                }
+            }
+        }
+    }
+
+    assert!(num_vcf_wins > 0);
+}
+
+#[test]
+pub fn test_tss_vcf_test() {
+    test_tss_vcf();
+}
+
+/// Test that defence_sqs finds exactly the moves that stop the opponent's threat sequences.
+pub fn test_defence_sqs() {
+    // WHITE has an open three.
+    let mut s = get_state(&["a1", "a15", "o1", "h12"], &["d4", "e4", "f4", "o15"], BLACK, true);
+    let result = defence_sqs(&mut s.board, s.turn, TSSMode::VCT, &TSSLimits::default());
+    assert!(result.threatened);
+    assert_eq!(result.defences, Vec::from([algebraic_to_point("c4"), algebraic_to_point("g4")]));
+
+    // Nothing to defend against.
+    let mut s = get_state(&["h8"], &["g7"], BLACK, true);
+    let result = defence_sqs(&mut s.board, s.turn, TSSMode::VCT, &TSSLimits::default());
+    assert!(!result.threatened);
+    assert!(result.defences.is_empty());
+}
+
+#[test]
+pub fn test_defence_sqs_test() {
+    test_defence_sqs();
+}
+
+/// Test that reduce_variations drops the composite and transposed variations.
+pub fn test_reduce_variations() {
+    // 4. Neuron (B) vs. Victoria (W)
+    let mut s = get_state(
+        &["h8", "g7", "i9", "i8", "f9", "i11", "h11", "h9", "j5"],
+        &["h7", "f6", "g8", "i6", "g9", "i10", "j11", "k4", "j7"],
+        BLACK,
+        true,
+    );
+
+    let variations = potential_win_variations(&tss_board(&mut s.board, s.turn));
+    let reduced = reduce_variations(&variations);
+    assert!(!reduced.is_empty() && reduced.len() < variations.len());
+    assert_eq!(reduced[0].len(), variations[0].len());
+
+    let sqs = reduced
+        .iter()
+        .map(|v| v.iter().map(|x| x.0).collect::<FnvHashSet<Point>>())
+        .collect::<Vec<FnvHashSet<Point>>>();
+    for (i, a) in sqs.iter().enumerate() {
+        for (j, b) in sqs.iter().enumerate() {
+            assert!(i == j || !a.is_subset(b));
+        }
+    }
+
+    // Every dropped variation extends (or transposes) a kept one.
+    for v in variations.iter() {
+        let v_sqs = v.iter().map(|x| x.0).collect::<FnvHashSet<Point>>();
+        assert!(sqs.iter().any(|x| x.is_subset(&v_sqs)));
+    }
+}
+
+#[test]
+pub fn test_reduce_variations_test() {
+    test_reduce_variations();
+}
+
+/// Set of (pattern index, segment) of the given threats, with segments in a canonical orientation.
+fn threat_keys<'a, I: Iterator<Item = &'a Threat>>(threats: I) -> FnvHashSet<(usize, Point, Point)> {
+    threats.map(|x| (x.pidx, x.m.0.min(x.m.1), x.m.0.max(x.m.1))).collect()
+}
+
+/// Test that the threat tracker agrees with a full board search after every move, and is restored by revert.
+pub fn test_threat_tracker() {
+    // 6. Xokk (B) vs. Victoria (W)
+    let s = get_state(
+        &["h8", "g7", "f8", "e9", "d10", "g6", "g9", "j8", "g8", "d8", "f10"],
+        &["h7", "f6", "i8", "h6", "c11", "g5", "i7", "f5", "g10", "e8"],
+        WHITE,
+        true,
+    );
+
+    let mut tracker = ThreatTracker::new(&s.board);
+    let moves = ["h9", "f7", "e7", "h5", "f9", "i6", "e10", "j6"];
+    let mut color = s.turn;
+
+    for m in moves.iter() {
+        tracker.apply(color, algebraic_to_point(m));
+        color ^= STONE;
+
+        for c in COLORS.iter() {
+            let expected = threat_keys(search_all_board(tracker.board(), *c, ThreatPri::Immediate).iter());
+            assert_eq!(threat_keys(tracker.threats(*c)), expected);
+            assert_eq!(tracker.num_threats(*c), expected.len());
+            assert_eq!(tracker.min_defcon(*c), tracker.threats(*c).fold(MAX_DEFCON, |a, b| a.min(b.defcon)));
+        }
+    }
+
+    tracker.revert_to(0);
+    assert_eq!(tracker.board(), &s.board);
+    for c in COLORS.iter() {
+        let expected = threat_keys(search_all_board(&s.board, *c, ThreatPri::Immediate).iter());
+        assert_eq!(threat_keys(tracker.threats(*c)), expected);
+    }
+}
+
+#[test]
+pub fn test_threat_tracker_test() {
+    test_threat_tracker();
+}
+
+/// Test that searching with a set of own sqs finds exactly the threats whose own_sqs are all drawn from the set.
+pub fn test_search_point_set_own() {
+    // 4. Neuron (B) vs. Victoria (W)
+    let s = get_state(
+        &["h8", "g7", "i9", "i8", "f9", "i11", "h11", "h9", "j5"],
+        &["h7", "f6", "g8", "i6", "g9", "i10", "j11", "k4", "j7"],
+        BLACK,
+        true,
+    );
+
+    // Own sqs of each color: all of the stones, some of the stones, and some stones along with some empty squares.
+    let point_sets = [
+        (BLACK, vec!["h8", "g7", "i9", "i8", "f9", "i11", "h11", "h9", "j5"]),
+        (BLACK, vec!["h8", "i9", "f9", "h11", "j5"]),
+        (BLACK, vec!["h8", "i8", "h9", "g9", "e4"]),
+        (WHITE, vec!["h7", "f6", "g8", "i6", "g9", "i10", "j11", "k4", "j7"]),
+        (WHITE, vec!["h7", "i6", "g8", "j11", "k4"]),
+    ];
+
+    for (c, points) in point_sets.iter() {
+        let points = points.iter().map(|x| algebraic_to_point(x)).collect::<FnvHashSet<Point>>();
+
+        let expected = search_all_board(&s.board, *c, ThreatPri::All)
+            .into_iter()
+            .filter(|x| PATTERNS[x.pidx].own_sqs.iter().all(|y| points.contains(&point_on_line(x.m.0, x.m.1, *y))))
+            .collect::<Vec<Threat>>();
+        let threats = search_all_point_set_own(&s.board, *c, &points, ThreatPri::All);
+        assert_eq!(threats.len(), expected.len());
+        assert_eq!(threat_keys(threats.iter()), threat_keys(expected.iter()));
+    }
+}
+
+#[test]
+pub fn test_search_point_set_own_test() {
+    test_search_point_set_own();
+}
+
+/// Test positions from Victoria's games (4th Computer Olympiad, London, 1992).
+pub fn victoria_states() -> Vec<State> {
+    vec![
+        // 1. Victoria (B) vs. Neuron (W)
+        get_state(
+            &["h8", "i7", "g9", "j6", "h6", "g6", "g8", "e8", "f7", "e6", "e5", "d6", "f4", "g4"],
+            &["i9", "h7", "f10", "k5", "j8", "i6", "g7", "f8", "d9", "d5", "e7", "f6", "g3", "i4"],
+            BLACK,
+            true,
+        ),
+        // 2. Victoria (B) vs. Zero Club (W)
+        get_state(&["h8", "g7", "f6", "g6", "i7", "f9"], &["h7", "i8", "i9", "i6", "g8", "f7"], BLACK, true),
+        // 3. Victoria (B) vs. Xokk (W)
+        get_state(&["h8", "g7", "i9", "h7", "h6", "i5"], &["g9", "f6", "j10", "i7", "h9", "f8"], BLACK, true),
+        // 4. Neuron (B) vs. Victoria (W)
+        get_state(
+            &["h8", "g7", "i9", "i8", "f9", "i11", "h11", "h9", "j5"],
+            &["h7", "f6", "g8", "i6", "g9", "i10", "j11", "k4", "j7"],
+            BLACK,
+            true,
+        ),
+        // 6. Xokk (B) vs. Victoria (W)
+        get_state(
+            &["h8", "g7", "f8", "e9", "d10", "g6", "g9", "j8", "g8", "d8", "f10"],
+            &["h7", "f6", "i8", "h6", "c11", "g5", "i7", "f5", "g10", "e8"],
+            WHITE,
+            true,
+        ),
+    ]
+}
+
+/// Game numbers (in the Olympiad) of the positions in victoria_states.
+pub const VICTORIA_GAMES: [usize; 5] = [1, 2, 3, 4, 6];
+
+/// Set of (pattern index, own_sqs, critical_sqs) of the given threats. Unlike threat_keys, this depends on the orientation of the matches.
+fn threat_sq_keys(threats: &[Threat]) -> FnvHashSet<(usize, Vec<Point>, Vec<Point>)> {
+    threats
+        .iter()
+        .map(|x| {
+            let own_sqs = PATTERNS[x.pidx]
+                .own_sqs
+                .iter()
+                .map(|y| point_on_line(x.m.0, x.m.1, *y))
+                .collect::<Vec<Point>>();
+            let mut critical_sqs = x.critical_sqs.iter().copied().collect::<Vec<Point>>();
+            critical_sqs.sort_unstable();
+            (x.pidx, own_sqs, critical_sqs)
+        })
+        .collect()
+}
+
+/// Test that pattern search on the bitboard gives the same results as on the array.
+pub fn test_bitboard() {
+    for s in victoria_states().iter() {
+        let mut bitboard = BitBoard::from_array(&s.board);
+        assert_eq!(bitboard.to_array(), s.board);
+
+        let p = algebraic_to_point("a1");
+        bitboard.set_sq(BLACK, p);
+        assert_eq!(bitboard.get(p), BLACK);
+        bitboard.clear_sq(BLACK, p);
+        assert_eq!(bitboard.to_array(), s.board);
+
+        for color in COLORS.iter() {
+            for pattern in PATTERNS.iter() {
+                let matches = bitboard.search_board(&pattern.pattern, *color);
+                let expected = search_board(&s.board, &pattern.pattern, *color);
+                assert_eq!(matches.len(), expected.len());
+                assert!(matches_are_equal(&matches, &expected));
+            }
+
+            let expected = search_all_board(&s.board, *color, ThreatPri::All);
+            assert_eq!(
+                threat_sq_keys(&bitboard.search_all_board(*color, ThreatPri::All)),
+                threat_sq_keys(&expected)
+            );
+
+            for x in 0..SIDE_LEN {
+                for y in 0..SIDE_LEN {
+                    let point = (x as isize, y as isize);
+
+                    let expected = search_all_point(&s.board, *color, point, ThreatPri::All);
+                    let threats = bitboard.search_all_point(*color, point, ThreatPri::All);
+                    assert_eq!(threats.len(), expected.len());
+                    assert_eq!(threat_sq_keys(&threats), threat_sq_keys(&expected));
+
+                    let expected = search_all_point_own(&s.board, *color, point, ThreatPri::All);
+                    let threats = bitboard.search_all_point_own(*color, point, ThreatPri::All);
+                    assert_eq!(threats.len(), expected.len());
+                    assert_eq!(threat_sq_keys(&threats), threat_sq_keys(&expected));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_bitboard_test() {
+    test_bitboard();
+}
+
+/// Benchmark Threat Space Search, and pattern search on the array, on the bitboard, and on the fixed board (with and without SIMD).
+/// Run with: cargo test --release bench_tss_board -- --ignored --nocapture
+pub fn bench_tss_board() {
+    const NUM_ITERS: u32 = 100;
+
+    for (game, s) in VICTORIA_GAMES.iter().zip(victoria_states().iter()) {
+        let mut board = s.board.to_owned();
+        let bitboard = BitBoard::from_array(&board);
+
+        let start = Instant::now();
+        for _ in 0..NUM_ITERS {
+            for color in COLORS.iter() {
+                search_all_board(&board, *color, ThreatPri::All);
+            }
+        }
+        let array_time = start.elapsed() / NUM_ITERS;
+
+        let start = Instant::now();
+        for _ in 0..NUM_ITERS {
+            for color in COLORS.iter() {
+                bitboard.search_all_board(*color, ThreatPri::All);
+            }
+        }
+        let bitboard_time = start.elapsed() / NUM_ITERS;
+
+        let fixed_board = FixedBoard::from_array(&board);
+        let start = Instant::now();
+        for _ in 0..NUM_ITERS {
+            for color in COLORS.iter() {
+                fixed_board.search_all_board(*color, ThreatPri::All);
+            }
+        }
+        let fixed_board_time = start.elapsed() / NUM_ITERS;
+
+        let start = Instant::now();
+        for _ in 0..NUM_ITERS {
+            for color in COLORS.iter() {
+                search_all_board_simd(&fixed_board, *color, ThreatPri::All);
+            }
+        }
+        let simd_time = start.elapsed() / NUM_ITERS;
+
+        let start = Instant::now();
+        let result = tss_board_limited(&mut board, s.turn, TSSMode::VCT, &TSSLimits::default());
+        let tss_time = start.elapsed();
+
+        println!(
+            "Game {}: search_all_board: array {:?}, bitboard {:?}, fixed board {:?}, simd {:?}; tss_board: {:?} ({} nodes)",
+            game, array_time, bitboard_time, fixed_board_time, simd_time, tss_time, result.num_nodes
+        );
+    }
+}
+
+#[test]
+#[ignore]
+pub fn bench_tss_board_test() {
+    bench_tss_board();
+}
+
+/// Benchmark Threat Space Search on thread pools of increasing size, up to the available parallelism,
+/// splitting the subtrees at depth 1 only (as in the original implementation) and at every depth up to TSS_PAR_BENCH_DEPTH.
+/// With a single thread, the subtrees are searched serially. Node counts must not depend on the number of threads or the depth.
+/// Run with: cargo test --release bench_tss_parallel -- --ignored --nocapture
+pub fn bench_tss_parallel() {
+    const NUM_ITERS: u32 = 20;
+    const TSS_PAR_BENCH_DEPTH: usize = 3;
+
+    let max_threads = std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1);
+    let mut num_threads = Vec::from([1]);
+    while num_threads[num_threads.len() - 1] * 2 < max_threads {
+        num_threads.push(num_threads[num_threads.len() - 1] * 2);
+    }
+    if max_threads > 1 {
+        num_threads.push(max_threads);
+    }
+
+    for (game, s) in VICTORIA_GAMES.iter().zip(victoria_states().iter()) {
+        let mut times: Vec<String> = Vec::new();
+        let mut num_nodes: Option<usize> = None;
+
+        for n in num_threads.iter() {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(*n).build().unwrap();
+            let mut board = s.board.to_owned();
+
+            for par_max_depth in [1, TSS_PAR_BENCH_DEPTH] {
+                let limits = TSSLimits {
+                    par_max_depth,
+                    ..TSSLimits::default()
+                };
+
+                let start = Instant::now();
+                for _ in 0..NUM_ITERS {
+                    let result = pool.install(|| tss_board_limited(&mut board, s.turn, TSSMode::VCT, &limits));
+                    assert_eq!(*num_nodes.get_or_insert(result.num_nodes), result.num_nodes);
+                }
+                times.push(format!("{} threads, depth {} {:?}", n, par_max_depth, start.elapsed() / NUM_ITERS));
+            }
+        }
+
+        println!("Game {}: {} ({} nodes)", game, times.join(", "), num_nodes.unwrap());
+    }
+}
+
+#[test]
+#[ignore]
+pub fn bench_tss_parallel_test() {
+    bench_tss_parallel();
+}
+
+/// Test that the table driven search gives the same results as search_point and search_point_next_sq.
+pub fn test_pattern_table() {
+    for s in victoria_states().iter() {
+        for color in COLORS.iter() {
+            for x in 0..SIDE_LEN {
+                for y in 0..SIDE_LEN {
+                    let point = (x as isize, y as isize);
+                    let matches = classify_point(&s.board, *color, point);
+                    let pairs = classify_point_next_sq(&s.board, *color, point);
+
+                    for p in PATTERNS.iter() {
+                        let expected = search_point(&s.board, &p.pattern, *color, point);
+                        assert_eq!(matches[p.index].len(), expected.len());
+                        assert!(matches_are_equal(&matches[p.index], &expected));
+
+                        let expected = search_point_next_sq(&s.board, &p.pattern, *color, point);
+                        assert_eq!(pairs[p.index].len(), expected.len());
+                        assert!(next_sq_matches_are_equal(&pairs[p.index], &expected));
+                    }
+
+                    let expected = search_all_point(&s.board, *color, point, ThreatPri::All);
+                    assert_eq!(
+                        threat_sq_keys(&search_all_point_table(&s.board, *color, point, ThreatPri::All)),
+                        threat_sq_keys(&expected)
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_pattern_table_test() {
+    test_pattern_table();
+}
+
+/// Check that two patterns are the same, including derived data.
+fn assert_patterns_equal(a: &Pattern, b: &Pattern) {
+    assert_eq!(a.pattern, b.pattern);
+    assert_eq!(a.critical_sqs, b.critical_sqs);
+    assert_eq!(a.own_sqs, b.own_sqs);
+    assert_eq!(a.empty_sqs, b.empty_sqs);
+    assert_eq!(a.defcon, b.defcon);
+    assert_eq!(a.immediate, b.immediate);
+    assert_eq!(a.name, b.name);
+    assert_eq!(a.index, b.index);
+}
+
+/// Test the text representation of patterns and pattern sets.
+pub fn test_pattern_text() {
+    assert_eq!(P_4_A.to_text(), "|xxxx*");
+    assert_eq!(P_3_B.to_text(), "*xx*x*");
+
+    let builtin = PatternSet::builtin();
+    for p in builtin.patterns.iter() {
+        assert_patterns_equal(&Pattern::from_text(&p.to_text(), p.name.to_owned(), p.index), p);
+    }
+
+    let parsed = PatternSet::from_text(&builtin.to_text()).unwrap();
+    assert_eq!(parsed.patterns.len(), builtin.patterns.len());
+    for (a, b) in parsed.patterns.iter().zip(builtin.patterns.iter()) {
+        assert_patterns_equal(a, b);
+    }
+
+    // The pattern file in the repository is the same as the built-in pattern set.
+    let from_file = PatternSet::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/patterns.txt")).unwrap();
+    assert_eq!(from_file.patterns.len(), builtin.patterns.len());
+    for (a, b) in from_file.patterns.iter().zip(builtin.patterns.iter()) {
+        assert_patterns_equal(a, b);
+    }
+
+    // Malformed pattern sets are reported with the line of the error.
+    let text = builtin.to_text();
+    let error_line = |bad: &str| {
+        let mut lines = text.lines().map(String::from).collect::<Vec<String>>();
+        lines.insert(3, String::from(bad));
+        PatternSet::from_text(&lines.join("\n")).unwrap_err().line
+    };
+    assert_eq!(error_line("P_BAD"), Some(4));
+    assert_eq!(error_line("P_BAD xx$xx"), Some(4));
+    assert_eq!(error_line("P_BAD xx"), Some(4));
+    assert_eq!(error_line("P_BAD _xx_x_o_x"), Some(4));
+    assert_eq!(error_line("P_BAD *xx_x*_"), Some(4));
+    assert_eq!(error_line("P_4_A |xxxx*"), Some(4));
+    assert_eq!(error_line("P_BAD _xxx____"), Some(4));
+
+    // Errors that aren't specific to a line.
+    let without_threes = text
+        .lines()
+        .filter(|x| !x.starts_with("P_3") && !x.starts_with("P_2"))
+        .collect::<Vec<&str>>();
+    assert_eq!(PatternSet::from_text(&without_threes.join("\n")).unwrap_err().line, None);
+    assert_eq!(PatternSet::from_file("/nonexistent/patterns.txt").unwrap_err().line, None);
+}
+
+#[test]
+pub fn test_pattern_text_test() {
+    test_pattern_text();
+}
+
+/// Test the derivation of critical squares against the hand-written ones.
+pub fn test_critical_sqs_analysis() {
+    // Known (and accepted) mismatch: the given list of P_2_B also has its outer squares. A placement there doesn't refute the two
+    // (it can still become a split three), but leaves it a single way to become a straight four.
+    let mismatches = critical_sqs_mismatches(&PATTERNS);
+    let names = mismatches.iter().map(|x| x.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(names, ["P_2_B"]);
+    assert_eq!(mismatches[0].given, [0, 1, 3, 5, 6]);
+    assert_eq!(mismatches[0].derived, [1, 3, 5]);
+
+    // A wrong list is flagged.
+    let mut p = P_3_A.to_owned();
+    p.critical_sqs = Vec::from([1, 5]);
+    assert_eq!(critical_sqs_mismatches(&[&p])[0].derived, [1, 5, 6]);
+}
+
+#[test]
+pub fn test_critical_sqs_analysis_test() {
+    test_critical_sqs_analysis();
+}
+
+/// Test that the completeness check finds no problems with the built-in patterns, and reports the problems of broken pattern sets.
+pub fn test_pattern_completeness() {
+    assert!(check_completeness(&PATTERNS, 10).is_empty());
+
+    // Without P_4_B, its fours are missed.
+    let patterns = PATTERNS.iter().filter(|x| x.name != "P_4_B").copied().collect::<Vec<&Pattern>>();
+    let reports = check_completeness(&patterns, 7);
+    assert!(!reports.is_empty());
+    assert!(reports.iter().all(|x| x.finding == Finding::Missed));
+    assert!(reports.iter().any(|x| x.line == [WALL, OWN, OWN, OWN, EMPTY, OWN, ENEMY]));
+
+    // A copy of P_3_ST counts its threes twice.
+    let copy = Pattern::from_text("_*xxx*_", String::from("P_3_ST_COPY"), PATTERNS.len());
+    let mut patterns = PATTERNS.to_owned();
+    patterns.push(&copy);
+    let reports = check_completeness(&patterns, 7);
+    assert!(!reports.is_empty());
+    assert!(reports.iter().all(|x| x.finding == Finding::DoubleCounted));
+
+    // P_3_C with a wrong critical square is misclassified.
+    let wrong = Pattern::from_text("|xxx*_", String::from("P_3_C"), P_3_C.index);
+    let patterns = PATTERNS
+        .iter()
+        .map(|&x| if x.index == wrong.index { &wrong } else { x })
+        .collect::<Vec<&Pattern>>();
+    let reports = check_completeness(&patterns, 7);
+    assert!(reports.iter().any(|x| x.finding == Finding::Misclassified));
+}
+
+#[test]
+pub fn test_pattern_completeness_test() {
+    test_pattern_completeness();
+}
+
+/// Test that find_fork classifies 4-4, 4-3 and 3-3 forks, and rejects moves that make a single threat (or a blocked one).
+pub fn test_find_fork() {
+    let h8 = algebraic_to_point("h8");
+    let fork_kind = |blacks: &[&str], whites: &[&str]| find_fork(&get_board(blacks, whites), BLACK, h8).map(|x| x.kind);
+
+    assert_eq!(fork_kind(&["e8", "f8", "g8", "h5", "h6", "h7"], &[]), Some(ForkKind::FourFour));
+    assert_eq!(fork_kind(&["e8", "f8", "g8", "h6", "h7"], &["d8"]), Some(ForkKind::FourThree));
+    assert_eq!(fork_kind(&["f8", "g8", "h6", "h7"], &[]), Some(ForkKind::ThreeThree));
+
+    // A single four, and a three along with a blocked three.
+    assert_eq!(fork_kind(&["e8", "f8", "g8"], &["d8"]), None);
+    assert_eq!(fork_kind(&["f8", "g8", "h6", "h7"], &["h5"]), None);
+
+    // The four of a four-three comes first.
+    let f = find_fork(&get_board(&["e8", "f8", "g8", "h6", "h7"], &["d8"]), BLACK, h8).unwrap();
+    assert_eq!((f.threats.0.defcon, f.threats.1.defcon), (1, 2));
+    assert_eq!(
+        f.threats.0.critical_sqs,
+        [algebraic_to_point("i8")].iter().copied().collect::<FnvHashSet<Point>>()
+    );
+
+    // The threats of a fork can't be defended against by a single move.
+    for s in victoria_states() {
+        for c in COLORS {
+            for (point, f) in fork_moves(&s.board, c) {
+                let threats = fork_threats(&s.board, c, point);
+                assert!(f.threats.0.critical_sqs.is_disjoint(&f.threats.1.critical_sqs));
+                assert!(threats.iter().all(|x| x.defcon == 1 || x.defcon == 2));
+                assert_eq!(f.kind == ForkKind::FourFour, f.threats.1.defcon == 1);
+                assert_eq!(f.kind == ForkKind::ThreeThree, f.threats.0.defcon == 2);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_find_fork_test() {
+    test_find_fork();
+}
+
+/// Test that classify_move reports the threats a move creates, strengthens, weakens or destroys, for both colors.
+pub fn test_classify_move() {
+    let effects = |blacks: &[&str], whites: &[&str], point: &str| {
+        classify_move(&get_board(blacks, whites), BLACK, algebraic_to_point(point))
+            .iter()
+            .map(|x| {
+                (
+                    x.color,
+                    x.effect,
+                    x.pattern.name.to_owned(),
+                    x.previous.as_ref().map(|y| PATTERNS[y.pidx].name.to_owned()),
+                )
+            })
+            .collect::<FnvHashSet<(u8, MoveEffect, String, Option<String>)>>()
+    };
+    let name = |x: &str| String::from(x);
+
+    // An open two becomes an open three.
+    let e = effects(&["f8", "g8"], &[], "h8");
+    assert!(e.contains(&(BLACK, MoveEffect::Strengthened, name("P_3_ST"), Some(name("P_2_A")))));
+    assert!(e
+        .iter()
+        .all(|x| x.0 == BLACK && x.1 != MoveEffect::Weakened && x.1 != MoveEffect::Destroyed));
+
+    // A closed three, out of stones that didn't make up a threat.
+    let e = effects(&["g8", "i8"], &["f8"], "h8");
+    assert!(e.contains(&(BLACK, MoveEffect::Created, name("P_3_C"), None)));
+
+    // Blocking one side of an open three.
+    let e = effects(&[], &["f8", "g8", "h8"], "i8");
+    assert!(e.contains(&(WHITE, MoveEffect::Weakened, name("P_3_C"), Some(name("P_3_ST")))));
+    assert!(e.iter().all(|x| x.0 == WHITE));
+
+    // Blocking a four.
+    let e = effects(&["d8"], &["e8", "f8", "g8", "h8"], "i8");
+    assert_eq!(e, [(WHITE, MoveEffect::Destroyed, name("P_4_A"), None)].iter().cloned().collect());
+
+    // Every threat found after the move is accounted for.
+    for s in victoria_states() {
+        for point in [algebraic_to_point("h10"), algebraic_to_point("e5")] {
+            if s.board[(point.0 as usize, point.1 as usize)] == EMPTY {
+                let changes = classify_move(&s.board, s.turn, point);
+                for c in COLORS {
+                    let mut next_board = s.board.to_owned();
+                    set_sq(&mut next_board, s.turn, point);
+                    let before = threat_keys(search_all_point(&s.board, c, point, ThreatPri::All).iter());
+                    let after = threat_keys(search_all_point(&next_board, c, point, ThreatPri::All).iter());
+                    let found = threat_keys(
+                        changes
+                            .iter()
+                            .filter(|x| x.color == c && x.effect != MoveEffect::Destroyed)
+                            .map(|x| &x.threat),
+                    );
+                    assert_eq!(found, after.difference(&before).cloned().collect());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_classify_move_test() {
+    test_classify_move();
+}
+
+/// Test that the search along an axis finds the matches of search_board that include the point and lie along the axis.
+pub fn test_search_point_axis() {
+    for s in victoria_states() {
+        for color in COLORS {
+            for p in PATTERNS.iter() {
+                let all_matches = search_board(&s.board, &p.pattern, color);
+
+                for ((x, y), elem) in s.board.indexed_iter() {
+                    let point = (x as isize, y as isize);
+
+                    for a in 0..NUM_AXES {
+                        let (line, pos) = line_at_point(&s.board, point, a);
+                        assert_eq!(line[pos], *elem);
+
+                        let expected = all_matches
+                            .iter()
+                            .filter(|m| {
+                                let (row_inc, col_inc) = increments(a);
+                                let (dx, dy) = (m.1 .0 - m.0 .0, m.1 .1 - m.0 .1);
+                                point_is_on_line(point, m.0, m.1, true) && dx * col_inc == dy * row_inc
+                            })
+                            .copied()
+                            .collect::<Vec<Match>>();
+                        let matches = search_point_axis(&s.board, &p.pattern, color, point, a);
+                        assert_eq!(matches.len(), expected.len());
+                        assert!(matches_are_equal(&matches, &expected));
+
+                        // The same matches, as positions on the line.
+                        let line_matches = search_line_at(&line, p.for_color(color), pos);
+                        assert_eq!(line_matches.len(), expected.len());
+                        assert!(line_matches.iter().all(|(start, end)| {
+                            let (row_inc, col_inc) = increments(a);
+                            let at = |k: usize| {
+                                (
+                                    point.0 + row_inc * (k as isize - pos as isize),
+                                    point.1 + col_inc * (k as isize - pos as isize),
+                                )
+                            };
+                            expected.contains(&(at(*start), at(*end)))
+                        }));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_search_point_axis_test() {
+    test_search_point_axis();
+}
+
+/// Test that the search on the fixed board gives the same results as the search on the array.
+pub fn test_fixed_board() {
+    for sq in 0..NUM_SQS {
+        let point = to_point(sq as Sq);
+        assert_eq!(to_sq(point) as usize, sq);
+
+        for d in 0..NUM_DIRECTIONS {
+            let (row_inc, col_inc) = increments(d);
+            let steps = STEPS[sq][d] as isize;
+            let end = (point.0 + row_inc * steps, point.1 + col_inc * steps);
+            assert_eq!(to_point(step(sq as Sq, d, steps)), end);
+            assert_eq!(sq as isize + DIR_OFFSETS[d] * steps, to_sq(end) as isize);
+            assert!([0, SIDE_LEN as isize - 1].contains(&end.0) || [0, SIDE_LEN as isize - 1].contains(&end.1));
+        }
+    }
+
+    for s in victoria_states().iter() {
+        let mut fixed_board = FixedBoard::from_array(&s.board);
+        assert_eq!(fixed_board.to_array(), s.board);
+
+        let sq = to_sq(algebraic_to_point("a1"));
+        fixed_board.set_sq(BLACK, sq);
+        assert_eq!(fixed_board.get(sq), BLACK);
+        fixed_board.clear_sq(BLACK, sq);
+        assert_eq!(fixed_board.to_array(), s.board);
+
+        for color in COLORS.iter() {
+            for pattern in PATTERNS.iter() {
+                let matches = fixed_board.search_board(&pattern.pattern, *color);
+                let expected = search_board(&s.board, &pattern.pattern, *color);
+                assert_eq!(matches.len(), expected.len());
+                assert!(matches_are_equal(&matches, &expected));
+            }
+
+            for sq in 0..NUM_SQS {
+                let point = to_point(sq as Sq);
+
+                let expected = search_all_point(&s.board, *color, point, ThreatPri::All);
+                let threats = fixed_board.search_all_point(*color, sq as Sq, ThreatPri::All);
+                assert_eq!(threats.len(), expected.len());
+                assert_eq!(threat_sq_keys(&threats), threat_sq_keys(&expected));
+
+                let expected = search_all_point_own(&s.board, *color, point, ThreatPri::All);
+                let threats = fixed_board.search_all_point_own(*color, sq as Sq, ThreatPri::All);
+                assert_eq!(threats.len(), expected.len());
+                assert_eq!(threat_sq_keys(&threats), threat_sq_keys(&expected));
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_fixed_board_test() {
+    test_fixed_board();
+}
+
+/// Test that the allocation-free search functions give the same results as the original ones, without any duplicates.
+pub fn test_search_into() {
+    for s in victoria_states().iter() {
+        let board = &s.board;
+
+        for color in COLORS {
+            for p in PATTERNS.iter() {
+                let pattern = p.for_color(color);
+                assert_eq!(pattern, get_pattern(&p.pattern, color));
+
+                let mut matches: Vec<Match> = Vec::new();
+                search_board_into(board, pattern, &mut matches);
+                let expected = search_board(board, &p.pattern, color);
+                assert_eq!(matches.len(), expected.len());
+                assert!(matches_are_equal(&matches, &expected));
+
+                let mut pairs: Vec<NSQMatch> = Vec::new();
+                search_board_next_sq_into(board, pattern, color, &mut pairs);
+                let expected = search_board_next_sq(board, &p.pattern, color);
+                assert_eq!(pairs.len(), expected.len());
+                assert!(next_sq_matches_are_equal(&pairs, &expected));
+
+                for x in 0..SIDE_LEN {
+                    for y in 0..SIDE_LEN {
+                        let point = (x as isize, y as isize);
+
+                        matches.clear();
+                        search_point_into(board, pattern, point, &mut matches);
+                        let expected = search_point(board, &p.pattern, color, point);
+                        assert_eq!(matches.len(), expected.len());
+                        assert!(matches_are_equal(&matches, &expected));
+
+                        matches.clear();
+                        search_point_own_into(board, pattern, color, point, &p.own_sqs, &mut matches);
+                        let expected = search_point_own(board, &p.pattern, color, point, &p.own_sqs);
+                        assert_eq!(matches.len(), expected.len());
+                        assert!(matches_are_equal(&matches, &expected));
+
+                        pairs.clear();
+                        search_point_next_sq_into(board, pattern, color, point, &mut pairs);
+                        let expected = search_point_next_sq(board, &p.pattern, color, point);
+                        assert_eq!(pairs.len(), expected.len());
+                        assert!(next_sq_matches_are_equal(&pairs, &expected));
+
+                        pairs.clear();
+                        search_point_own_next_sq_into(board, pattern, color, point, &p.own_sqs, &mut pairs);
+                        let expected = search_point_own_next_sq(board, &p.pattern, color, point, &p.own_sqs);
+                        assert_eq!(pairs.len(), expected.len());
+                        assert!(next_sq_matches_are_equal(&pairs, &expected));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_search_into_test() {
+    test_search_into();
+}
+
+/// Test that the SIMD search (and its scalar fallback) gives exactly the same matches as the search on the array.
+pub fn test_simd_search() {
+    let mut boards = victoria_states().into_iter().map(|s| s.board).collect::<Vec<Array2<u8>>>();
+
+    // Stones right next to the walls, where lines wrap around in the flat layout.
+    let edges = ["a1", "a2", "a3", "a4", "b1", "o15", "o14", "o13", "n15", "a15", "b14", "o1", "o2"];
+    boards.push(get_board(&edges, &["h8", "h9", "h10", "h11"]));
+    boards.push(get_board(&["h8", "h9", "h10", "h11"], &edges));
+
+    for board in boards.iter() {
+        let fixed_board = FixedBoard::from_array(board);
+
+        for color in COLORS {
+            for p in PATTERNS.iter() {
+                let pattern = p.for_color(color);
+
+                let mut expected: Vec<Match> = Vec::new();
+                search_board_into(board, pattern, &mut expected);
+                expected.sort_unstable();
+
+                let mut matches: Vec<Match> = Vec::new();
+                search_board_simd_into(&fixed_board, pattern, &mut matches);
+                matches.sort_unstable();
+                assert_eq!(matches, expected);
+
+                matches.clear();
+                search_board_scalar_into(&fixed_board, pattern, &mut matches);
+                matches.sort_unstable();
+                assert_eq!(matches, expected);
+            }
+
+            let expected = search_all_board(board, color, ThreatPri::All);
+            let threats = search_all_board_simd(&fixed_board, color, ThreatPri::All);
+            assert_eq!(threats.len(), expected.len());
+            assert_eq!(threat_sq_keys(&threats), threat_sq_keys(&expected));
+        }
+    }
+}
+
+#[test]
+pub fn test_simd_search_test() {
+    test_simd_search();
+}
+
+/// Test the static evaluation: the mate scores, and that the evaluation doesn't depend on the color of the side to move.
+pub fn test_evaluate() {
+    // Mate scores are ordered by distance, and above (below) all heuristic scores.
+    assert!(win_in(1) > win_in(3) && win_in(3) > MAX_EVAL);
+    assert!(loss_in(4) > loss_in(2) && -MAX_EVAL > loss_in(4));
+    assert_eq!(mate_plies(win_in(3)), Some(3));
+    assert_eq!(mate_plies(loss_in(2)), Some(-2));
+    assert_eq!(mate_plies(MAX_EVAL), None);
+
+    // Empty board.
+    assert_eq!(evaluate(&get_state(&[], &[], BLACK, true)), TEMPO_BONUS);
+
+    // A four of the side to move.
+    let s = get_state(&["h8", "h9", "h10", "h11", "a1"], &["g8", "g9", "g10", "i11"], WHITE, false);
+    assert_eq!(evaluate(&s), loss_in(2));
+    assert_eq!(evaluate(&State::new(s.board.to_owned(), BLACK, false)), win_in(1));
+
+    // A four of the opponent that can be blocked.
+    let s = get_state(&["h8", "h9", "h10", "h11", "a1"], &["h7", "g9", "g10", "i11"], WHITE, false);
+    assert!(!is_mate_score(evaluate(&s)));
+    assert!(evaluate(&s) < 0);
+
+    // An open three is better than a closed three, which is better than nothing.
+    let open = evaluate(&get_state(&["h8", "h9", "h10"], &["a1", "a3"], BLACK, false));
+    let closed = evaluate(&get_state(&["h8", "h9", "h10"], &["h7", "a3"], BLACK, false));
+    assert!(open > closed && closed > TEMPO_BONUS);
+    assert!(evaluate(&get_state(&["h8", "h9", "h10"], &["a1", "a3"], WHITE, false)) < 0);
+
+    // A finished game.
+    let s = get_state(&["h8", "h9", "h10", "h11", "h12"], &["a1", "a3", "a5", "a7"], WHITE, true);
+    assert_eq!(evaluate(&s), loss_in(0));
+
+    // Swapping the colors of all the stones and the side to move doesn't change the evaluation.
+    for s in victoria_states() {
+        let board = s.board.mapv(|x| match x {
+            BLACK => WHITE,
+            WHITE => BLACK,
+            _ => x,
+        });
+        let turn = if s.turn == BLACK { WHITE } else { BLACK };
+        assert_eq!(evaluate(&State::new(board, turn, false)), evaluate(&s));
+    }
+}
+
+#[test]
+pub fn test_evaluate_test() {
+    test_evaluate();
+}
+
+/// Test the engine search on positions where the best move (or the outcome) is known.
+pub fn test_engine_search() {
+    let limits = EngineLimits::new(Some(2), None, None);
+
+    // BLACK makes five.
+    let s = get_state(&["h8", "h9", "h10", "h11", "a1"], &["g8", "g9", "g10", "i11"], BLACK, false);
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert!(["h7", "h12"].contains(&point_to_algebraic(result.best_move.unwrap()).as_str()));
+    assert_eq!(result.score, win_in(1));
+    assert_eq!(result.pv[0], result.best_move.unwrap());
+
+    // WHITE has to block the four.
+    let s = get_state(&["h8", "h9", "h10", "h11", "a1"], &["h7", "g9", "g10", "i11"], WHITE, false);
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert_eq!(result.best_move, Some(algebraic_to_point("h12")));
+    assert!(!is_mate_score(result.score));
+
+    // WHITE can't stop a straight four.
+    let s = get_state(&["h8", "h9", "h10", "h11", "a1"], &["a3", "g9", "g10", "i11"], WHITE, false);
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert_eq!(result.score, loss_in(2));
+
+    // 6. Xokk (B) vs. Victoria (W): WHITE has a potential win, found by the TSS probe.
+    // The probe puts it first, but doesn't score it as a mate: that takes a deep enough search.
+    let s = &victoria_states()[4];
+    let mut board = s.board.to_owned();
+    let tss_result = tss_board_limited(&mut board, s.turn, TSSMode::VCT, &TSSLimits::default());
+    let first_sqs = potential_win_variations(&tss_result.tree).iter().map(|x| x[0].0).collect::<Vec<Point>>();
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert!(!is_mate_score(result.score));
+    assert!(first_sqs.contains(&result.best_move.unwrap()));
+    let result = engine_search(&s.board, s.turn, &EngineLimits::new(Some(4), None, None));
+    assert_eq!(mate_plies(result.score), Some(5));
+    assert_eq!(result.depth, 3);
+    assert!(first_sqs.contains(&result.best_move.unwrap()));
+
+    // A quiet position: the result of the deepest completed iteration.
+    let s = get_state(&["h8", "i9"], &["g7", "j8"], BLACK, true);
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert_eq!(result.depth, 2);
+    assert!(!is_mate_score(result.score));
+    assert_eq!(result.pv.first().copied(), result.best_move);
+    assert!(result.pv.len() <= 2);
+
+    // With no time at all, the best move is the first of the candidate moves.
+    let limits = EngineLimits::new(None, None, Some(Instant::now()));
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert_eq!(result.depth, 0);
+    assert_eq!(result.best_move, candidate_moves(&s.board, s.turn).first().copied());
+
+    // The node limit is honoured.
+    let limits = EngineLimits::new(None, Some(50), None);
+    let result = engine_search(&s.board, s.turn, &limits);
+    assert!(result.num_nodes <= 50);
+    assert!(result.best_move.is_some());
+
+    // On an empty board, the first move is in the center.
+    let s = get_state(&[], &[], BLACK, true);
+    assert_eq!(engine_search(&s.board, s.turn, &limits).best_move, Some(algebraic_to_point("h8")));
+}
+
+#[test]
+pub fn test_engine_search_test() {
+    test_engine_search();
+}
@@ -2,72 +2,395 @@
 
 use crate::board::point_to_algebraic;
 use crate::board::{board_to_str, clear_sq, set_sq};
-use crate::consts::{ANIMATION_TIMESTEP_SECS, MAX_DEFCON, STONE};
-use crate::geometry::{point_is_on_line, Point};
-use crate::pattern::Threat;
-use crate::pattern::{
-    search_all_board, search_all_board_get_next_sqs, search_all_point, search_all_point_own, search_all_point_own_get_next_sqs, ThreatPri,
-};
+use crate::consts::{ANIMATION_TIMESTEP_SECS, STONE, TSS_PAR_MAX_DEPTH, TSS_PAR_MIN_CHILDREN};
+use crate::geometry::Point;
+use crate::pattern::{search_all_board_get_next_sqs, search_all_point_own_get_next_sqs, ThreatPri};
+use crate::pattern::{Threat, NUM_PTNS, PATTERNS};
+use crate::threat_tracker::ThreatTracker;
 use fnv::FnvHashSet;
 use ndarray::prelude::*;
 use rayon::prelude::*;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-/// A tree that represents the result of a Threat Space Search.
+/// Reason for cutting off a branch of the Threat Space Search, along with the offending opponent threat(s) if any.
+#[derive(Clone, Debug)]
+pub enum TSSCutoff {
+    /// After next_sq, the opponent has a threat at least as fast as all of ours.
+    OppFasterThreat(Threat),
+    /// The opponent completed five while covering the critical_sqs.
+    OppWonThroughCriticalSqs(Threat),
+    /// After covering the critical_sqs, the opponent has pressing threats that can't all be stopped by one move.
+    OppUnstoppableThreats(Vec<Threat>),
+    /// The node would have been expanded, but it is at the max_depth.
+    MaxDepth,
+    /// The search was stopped by the node limit, the deadline or a cancellation.
+    Stopped,
+}
+
+/// A node of the Threat Space Search tree.
+///
+/// Nodes live in a SearchTree, and refer to their critical_sqs and children through ranges into the tree's flat vectors.
 #[derive(Clone, Debug)]
 pub struct SearchNode {
     pub next_sq: Option<Point>,
-    pub critical_sqs: Option<FnvHashSet<Point>>,
     pub potential_win: bool,
-    pub children: Vec<SearchNode>,
+    pub cutoff: Option<TSSCutoff>,
+    critical_sqs: (u32, u32),
+    children: (u32, u32),
 }
 
-impl SearchNode {
-    #[inline(always)]
-    pub fn new(next_sq: Option<Point>, critical_sqs: Option<FnvHashSet<Point>>, potential_win: bool, children: Vec<SearchNode>) -> Self {
-        Self {
+/// A tree that represents the result of a Threat Space Search.
+///
+/// The nodes are stored in a single arena, in depth-first pre-order, with the root at index 0.
+/// The critical_sqs of all the nodes are stored in one flat vector, and so are the indices of the children,
+/// so a search makes only a handful of allocations, and the tree is easy to serialise.
+#[derive(Clone, Debug, Default)]
+pub struct SearchTree {
+    nodes: Vec<SearchNode>,
+    sqs: Vec<Point>,
+    edges: Vec<u32>,
+}
+
+impl SearchTree {
+    pub const ROOT: usize = 0;
+
+    pub fn root(&self) -> &SearchNode {
+        &self.nodes[Self::ROOT]
+    }
+
+    pub fn node(&self, idx: usize) -> &SearchNode {
+        &self.nodes[idx]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// critical_sqs of the given node. Empty for the root.
+    pub fn critical_sqs(&self, idx: usize) -> &[Point] {
+        let (start, end) = self.nodes[idx].critical_sqs;
+        &self.sqs[start as usize..end as usize]
+    }
+
+    /// Indices of the children of the given node.
+    pub fn children(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let (start, end) = self.nodes[idx].children;
+        self.edges[start as usize..end as usize].iter().map(|&x| x as usize)
+    }
+
+    /// All the nodes (along with their indices) in depth-first pre-order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &SearchNode)> {
+        self.nodes.iter().enumerate()
+    }
+
+    /// Add a node without children, and return its index.
+    fn push(&mut self, next_sq: Option<Point>, critical_sqs: &FnvHashSet<Point>, potential_win: bool, cutoff: Option<TSSCutoff>) -> usize {
+        let csqs_start = self.sqs.len() as u32;
+        self.sqs.extend(critical_sqs.iter());
+        let edges_end = self.edges.len() as u32;
+
+        self.nodes.push(SearchNode {
             next_sq,
-            critical_sqs,
             potential_win,
-            children,
+            cutoff,
+            critical_sqs: (csqs_start, self.sqs.len() as u32),
+            children: (edges_end, edges_end),
+        });
+
+        self.nodes.len() - 1
+    }
+
+    /// Add a leaf node for next_sq that was cut off for the given reason, and return its index.
+    fn push_cut_off(&mut self, next_sq: Point, cutoff: TSSCutoff) -> usize {
+        self.push(Some(next_sq), &FnvHashSet::<Point>::default(), false, Some(cutoff))
+    }
+
+    /// Set the children of the given node. Can only be done once per node.
+    fn set_children(&mut self, idx: usize, children: &[usize]) {
+        assert_eq!(self.nodes[idx].children.0, self.nodes[idx].children.1);
+
+        let edges_start = self.edges.len() as u32;
+        self.edges.extend(children.iter().map(|&x| x as u32));
+        self.nodes[idx].children = (edges_start, self.edges.len() as u32);
+    }
+
+    /// Append a tree that was searched separately, and return the index of its root.
+    fn append(&mut self, other: SearchTree) -> usize {
+        let nodes_offset = self.nodes.len() as u32;
+        let sqs_offset = self.sqs.len() as u32;
+        let edges_offset = self.edges.len() as u32;
+
+        self.nodes.extend(other.nodes.into_iter().map(|mut x| {
+            x.critical_sqs = (x.critical_sqs.0 + sqs_offset, x.critical_sqs.1 + sqs_offset);
+            x.children = (x.children.0 + edges_offset, x.children.1 + edges_offset);
+            x
+        }));
+        self.sqs.extend(other.sqs);
+        self.edges.extend(other.edges.into_iter().map(|x| x + nodes_offset));
+
+        nodes_offset as usize
+    }
+}
+
+/// Limits on a Threat Space Search. None means no limit.
+///
+/// The search can be cancelled from another thread by setting the cancel flag.
+/// Subtrees are searched in parallel at depths up to par_max_depth (TSS_PAR_MAX_DEPTH by default). See tss_children.
+#[derive(Clone, Debug)]
+pub struct TSSLimits {
+    pub max_depth: Option<usize>,
+    pub max_nodes: Option<usize>,
+    pub deadline: Option<Instant>,
+    pub cancel: Arc<AtomicBool>,
+    pub par_max_depth: usize,
+}
+
+impl TSSLimits {
+    pub fn new(max_depth: Option<usize>, max_nodes: Option<usize>, deadline: Option<Instant>, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            max_depth,
+            max_nodes,
+            deadline,
+            cancel,
+            par_max_depth: TSS_PAR_MAX_DEPTH,
         }
     }
 }
 
+impl Default for TSSLimits {
+    fn default() -> Self {
+        Self::new(None, None, None, Arc::new(AtomicBool::new(false)))
+    }
+}
+
+/// Mode of a Threat Space Search.
+///
+/// VCF (Victory by Continuous Fours): every attacking move must make a four (or five).
+/// VCT (Victory by Continuous Threats): attacking moves may make any threat, including threes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TSSMode {
+    VCF,
+    VCT,
+}
+
+impl TSSMode {
+    /// Priorities of the patterns used to generate attacking moves, in the order in which they are tried.
+    pub fn next_sq_pris(self) -> &'static [ThreatPri] {
+        match self {
+            TSSMode::VCF => &[ThreatPri::Four],
+            TSSMode::VCT => &[ThreatPri::Immediate, ThreatPri::NonImmediate],
+        }
+    }
+}
+
+/// State shared by all the nodes of a single Threat Space Search.
+#[derive(Debug)]
+pub struct TSSContext<'a> {
+    pub mode: TSSMode,
+    pub limits: &'a TSSLimits,
+    pub num_nodes: AtomicUsize,
+    pub cut_off: AtomicBool,
+}
+
+impl<'a> TSSContext<'a> {
+    pub fn new(mode: TSSMode, limits: &'a TSSLimits) -> Self {
+        Self {
+            mode,
+            limits,
+            num_nodes: AtomicUsize::new(0),
+            cut_off: AtomicBool::new(false),
+        }
+    }
+
+    /// Count a new node, and check if the search has to stop (node limit, deadline or cancellation).
+    pub fn stop(&self) -> bool {
+        let num_nodes = self.num_nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        let stop = self.limits.cancel.load(Ordering::Relaxed)
+            || self.limits.max_nodes.is_some_and(|x| num_nodes > x)
+            || self.limits.deadline.is_some_and(|x| Instant::now() >= x);
+
+        if stop {
+            self.cut_off.store(true, Ordering::Relaxed);
+        }
+
+        stop
+    }
+
+    /// Check if a node at the given depth is too deep to be expanded.
+    pub fn depth_reached(&self, depth: usize) -> bool {
+        let reached = self.limits.max_depth.is_some_and(|x| depth >= x);
+
+        if reached {
+            self.cut_off.store(true, Ordering::Relaxed);
+        }
+
+        reached
+    }
+}
+
+/// Statistics collected during a Threat Space Search.
+///
+/// Times are summed over all the threads.
+/// node_time is the time spent in the nodes themselves (excluding their children), and includes search_time,
+/// which is the time spent in pattern search.
+#[derive(Clone, Debug, Default)]
+pub struct TSSStats {
+    pub nodes_per_depth: Vec<usize>,
+    pub potential_loss_cuts: usize,
+    pub threats_per_pattern: Vec<usize>,
+    pub search_time: Duration,
+    pub node_time: Duration,
+    pub max_branching_factor: usize,
+}
+
+impl TSSStats {
+    /// Count a node visited at the given depth.
+    pub fn visit(&mut self, depth: usize) {
+        if self.nodes_per_depth.len() <= depth {
+            self.nodes_per_depth.resize(depth + 1, 0);
+        }
+        self.nodes_per_depth[depth] += 1;
+    }
+
+    /// Count the threats found, given their pattern indices.
+    pub fn count_threats<I: Iterator<Item = usize>>(&mut self, pidxs: I) {
+        if self.threats_per_pattern.is_empty() {
+            self.threats_per_pattern.resize(*NUM_PTNS, 0);
+        }
+        for pidx in pidxs {
+            self.threats_per_pattern[pidx] += 1;
+        }
+    }
+
+    /// Run a pattern search, adding the time taken to search_time, and counting the threats found.
+    #[inline(always)]
+    pub fn search_threats<F: FnOnce() -> Vec<Threat>>(&mut self, search: F) -> Vec<Threat> {
+        let start = Instant::now();
+        let threats = search();
+        self.search_time += start.elapsed();
+        self.count_threats(threats.iter().map(|x| x.pidx));
+        threats
+    }
+
+    /// Make a move on the tracker, adding the time taken to update the threats to search_time, and counting the new threats.
+    #[inline(always)]
+    pub fn apply_move(&mut self, tracker: &mut ThreatTracker, color: u8, point: Point) {
+        let start = Instant::now();
+        tracker.apply(color, point);
+        self.search_time += start.elapsed();
+        self.count_threats(tracker.last_added_pidxs());
+    }
+
+    /// Run a next_sq search, adding the time taken to search_time.
+    #[inline(always)]
+    pub fn search_next_sqs<F: FnOnce() -> FnvHashSet<Point>>(&mut self, search: F) -> FnvHashSet<Point> {
+        let start = Instant::now();
+        let nsqs = search();
+        self.search_time += start.elapsed();
+        nsqs
+    }
+
+    /// Add the statistics of a subtree that was searched separately.
+    pub fn merge(&mut self, other: &TSSStats) {
+        if self.nodes_per_depth.len() < other.nodes_per_depth.len() {
+            self.nodes_per_depth.resize(other.nodes_per_depth.len(), 0);
+        }
+        for (i, x) in other.nodes_per_depth.iter().enumerate() {
+            self.nodes_per_depth[i] += x;
+        }
+
+        if self.threats_per_pattern.len() < other.threats_per_pattern.len() {
+            self.threats_per_pattern.resize(other.threats_per_pattern.len(), 0);
+        }
+        for (i, x) in other.threats_per_pattern.iter().enumerate() {
+            self.threats_per_pattern[i] += x;
+        }
+
+        self.potential_loss_cuts += other.potential_loss_cuts;
+        self.search_time += other.search_time;
+        self.node_time += other.node_time;
+        self.max_branching_factor = self.max_branching_factor.max(other.max_branching_factor);
+    }
+}
+
+impl fmt::Display for TSSStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut output = String::new();
+        output.push_str(&format!("nodes_per_depth: {:?}\n", self.nodes_per_depth));
+        output.push_str(&format!("potential_loss_cuts: {}\n", self.potential_loss_cuts));
+        output.push_str(&format!("max_branching_factor: {}\n", self.max_branching_factor));
+        output.push_str(&format!("search_time: {} seconds\n", self.search_time.as_secs_f32()));
+        output.push_str(&format!(
+            "other_time: {} seconds\n",
+            self.node_time.saturating_sub(self.search_time).as_secs_f32()
+        ));
+        output.push_str("threats_per_pattern:\n");
+        for (i, x) in self.threats_per_pattern.iter().enumerate() {
+            output.push_str(&format!("    {}: {}\n", PATTERNS[i].name, x));
+        }
+
+        write!(f, "{}", output)
+    }
+}
+
+/// Result of a Threat Space Search.
+/// If the search was cut off by any of the limits, then finished is false.
+#[derive(Clone, Debug)]
+pub struct TSSResult {
+    pub tree: SearchTree,
+    pub finished: bool,
+    pub num_nodes: usize,
+    pub stats: TSSStats,
+}
+
 /// Threat Space Search for a given next_sq.
-pub fn tss_next_sq(board: &mut Array2<u8>, color: u8, next_sq: Point, all_threats_init: &[Threat], opp_all_threats_init: &[Threat]) -> SearchNode {
-    set_sq(board, color, next_sq);
+///
+/// depth is the number of moves made by color so far, including next_sq.
+/// The resulting subtree is added to tree, and the index of its root is returned.
+/// tracker is restored to its original state before returning.
+pub fn tss_next_sq(
+    tracker: &mut ThreatTracker,
+    color: u8,
+    next_sq: Point,
+    depth: usize,
+    ctx: &TSSContext,
+    stats: &mut TSSStats,
+    tree: &mut SearchTree,
+) -> usize {
+    if ctx.stop() {
+        return tree.push_cut_off(next_sq, TSSCutoff::Stopped);
+    }
 
-    // Create all_threats for self and opponent, and update them.
-    // 1. Remove threats including next_sq.
-    // 2. Compute new threats including next_sq.
-    let mut all_threats = all_threats_init
-        .iter()
-        .filter(|x| !point_is_on_line(next_sq, x.m.0, x.m.1, true))
-        .cloned()
-        .collect::<Vec<Threat>>();
-    all_threats.extend(search_all_point(board, color, next_sq, ThreatPri::Immediate));
+    let start = Instant::now();
+    stats.visit(depth);
 
-    let mut opp_all_threats = opp_all_threats_init
-        .iter()
-        .filter(|x| !point_is_on_line(next_sq, x.m.0, x.m.1, true))
-        .cloned()
-        .collect::<Vec<Threat>>();
-    opp_all_threats.extend(search_all_point(board, color ^ STONE, next_sq, ThreatPri::Immediate));
+    // The tracker updates the threats of both colors passing through next_sq.
+    let num_moves = tracker.num_moves();
+    stats.apply_move(tracker, color, next_sq);
 
     // NOTE: If we are potentially losing, we will early return.
 
-    // Check if we are potentially losing, by looking at the updated lists of all threats.
-    let mut min_defcon = all_threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
-    let mut opp_min_defcon = opp_all_threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
-    let mut potential_loss = !opp_all_threats.is_empty() && opp_min_defcon <= min_defcon;
+    // Check if we are potentially losing, by looking at the updated threats.
+    let mut min_defcon = tracker.min_defcon(color);
+    let mut opp_min_defcon = tracker.min_defcon(color ^ STONE);
+    let mut potential_loss = tracker.num_threats(color ^ STONE) > 0 && opp_min_defcon <= min_defcon;
     if potential_loss {
-        clear_sq(board, color, next_sq);
-        return SearchNode::new(Some(next_sq), Some(FnvHashSet::<Point>::default()), false, Vec::<SearchNode>::new());
+        let opp_threat = tracker.threats(color ^ STONE).find(|x| x.defcon == opp_min_defcon).unwrap().to_owned();
+        tracker.revert_to(num_moves);
+        stats.potential_loss_cuts += 1;
+        stats.node_time += start.elapsed();
+        return tree.push_cut_off(next_sq, TSSCutoff::OppFasterThreat(opp_threat));
     }
 
-    let threats = search_all_point_own(board, color, next_sq, ThreatPri::Immediate);
+    let threats = stats.search_threats(|| tracker.bitboard().search_all_point_own(color, next_sq, ThreatPri::Immediate));
 
     // We will consider those of our threats which are more immediate than all of our opponent's threats.
     let pressing_threats = threats.iter().filter(|x| x.defcon < opp_min_defcon).cloned().collect::<Vec<Threat>>();
@@ -83,44 +406,30 @@ pub fn tss_next_sq(board: &mut Array2<u8>, color: u8, next_sq: Point, all_threat
     };
 
     for csq in critical_sqs.iter() {
-        set_sq(board, color ^ STONE, *csq);
+        stats.apply_move(tracker, color ^ STONE, *csq);
     }
 
-    // If we have any critical_sqs, update lists of all threats.
-    // Also check if we are potentially losing after the critical_sqs are covered by the opponent.
+    // If we have any critical_sqs, check if we are potentially losing after they are covered by the opponent.
     if !critical_sqs.is_empty() {
-        all_threats = all_threats
-            .iter()
-            .filter(|x| !critical_sqs.iter().any(|p| point_is_on_line(*p, x.m.0, x.m.1, true)))
-            .cloned()
-            .collect::<Vec<Threat>>();
-
-        opp_all_threats = opp_all_threats
-            .iter()
-            .filter(|x| !critical_sqs.iter().any(|p| point_is_on_line(*p, x.m.0, x.m.1, true)))
-            .cloned()
-            .collect::<Vec<Threat>>();
-
-        for csq in critical_sqs.iter() {
-            all_threats.extend(search_all_point(board, color, *csq, ThreatPri::Immediate));
-            opp_all_threats.extend(search_all_point(board, color ^ STONE, *csq, ThreatPri::Immediate));
-        }
-
-        min_defcon = all_threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
-        opp_min_defcon = opp_all_threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
+        min_defcon = tracker.min_defcon(color);
+        opp_min_defcon = tracker.min_defcon(color ^ STONE);
 
         // If opp_min_defcon is 0, then the opponent has potentially won!
         potential_loss = opp_min_defcon == 0;
         if potential_loss {
-            for csq in critical_sqs.iter() {
-                clear_sq(board, color ^ STONE, *csq);
-            }
-            clear_sq(board, color, next_sq);
-            return SearchNode::new(Some(next_sq), Some(FnvHashSet::<Point>::default()), false, Vec::<SearchNode>::new());
+            let opp_threat = tracker.threats(color ^ STONE).find(|x| x.defcon == 0).unwrap().to_owned();
+            tracker.revert_to(num_moves);
+            stats.potential_loss_cuts += 1;
+            stats.node_time += start.elapsed();
+            return tree.push_cut_off(next_sq, TSSCutoff::OppWonThroughCriticalSqs(opp_threat));
         }
 
         // We will consider those of the opponent's threats which are more immediate than all of our threats.
-        let opp_pressing_threats = opp_all_threats.iter().filter(|x| x.defcon < min_defcon).cloned().collect::<Vec<Threat>>();
+        let opp_pressing_threats = tracker
+            .threats(color ^ STONE)
+            .filter(|x| x.defcon < min_defcon)
+            .cloned()
+            .collect::<Vec<Threat>>();
         let opp_critical_sqs: FnvHashSet<Point> = if !opp_pressing_threats.is_empty() {
             opp_pressing_threats
                 .iter()
@@ -133,94 +442,170 @@ pub fn tss_next_sq(board: &mut Array2<u8>, color: u8, next_sq: Point, all_threat
 
         potential_loss = !opp_pressing_threats.is_empty() && opp_critical_sqs.is_empty();
         if potential_loss {
-            for csq in critical_sqs.iter() {
-                clear_sq(board, color ^ STONE, *csq);
-            }
-            clear_sq(board, color, next_sq);
-            return SearchNode::new(Some(next_sq), Some(FnvHashSet::<Point>::default()), false, Vec::<SearchNode>::new());
+            tracker.revert_to(num_moves);
+            stats.potential_loss_cuts += 1;
+            stats.node_time += start.elapsed();
+            return tree.push_cut_off(next_sq, TSSCutoff::OppUnstoppableThreats(opp_pressing_threats));
         }
     }
 
     let mut potential_win = !pressing_threats.is_empty() && critical_sqs.is_empty();
-    let mut children = Vec::<SearchNode>::new();
+    let idx = tree.push(Some(next_sq), &critical_sqs, potential_win, None);
+    let mut children = Vec::<usize>::new();
+    let mut children_time = Duration::ZERO;
+    let mut cutoff: Option<TSSCutoff> = None;
 
     // If next_sq produces no threats or we've found a potential win, we won't go any deeper.
-    if !threats.is_empty() && !potential_win {
-        let nsqs = search_all_point_own_get_next_sqs(board, color, next_sq, ThreatPri::Immediate);
-        children = nsqs
-            .iter()
-            .map(|x| tss_next_sq(board, color, *x, &all_threats, &opp_all_threats))
-            .collect();
-        potential_win = children.iter().any(|x| x.potential_win);
-
-        if !potential_win {
-            let nsqs_other = search_all_point_own_get_next_sqs(board, color, next_sq, ThreatPri::NonImmediate);
-            let children_other: Vec<SearchNode> = nsqs_other
-                .iter()
-                .map(|x| tss_next_sq(board, color, *x, &all_threats, &opp_all_threats))
-                .collect();
-            potential_win = children_other.iter().any(|x| x.potential_win);
-            children.extend(children_other);
+    // Lower priority next_sqs are only tried if the higher priority ones don't lead to a potential win.
+    if !threats.is_empty() && !potential_win && ctx.depth_reached(depth) {
+        cutoff = Some(TSSCutoff::MaxDepth);
+    } else if !threats.is_empty() && !potential_win {
+        for pri in ctx.mode.next_sq_pris() {
+            let nsqs = stats.search_next_sqs(|| search_all_point_own_get_next_sqs(tracker.board(), color, next_sq, *pri));
+            let children_start = Instant::now();
+            children.extend(tss_children(tracker, color, &nsqs, depth + 1, ctx, stats, tree));
+            children_time += children_start.elapsed();
+            potential_win = children.iter().any(|&x| tree.node(x).potential_win);
+
+            if potential_win {
+                break;
+            }
         }
     }
 
-    for csq in critical_sqs.iter() {
-        clear_sq(board, color ^ STONE, *csq);
-    }
-    clear_sq(board, color, next_sq);
+    tracker.revert_to(num_moves);
+
+    stats.max_branching_factor = stats.max_branching_factor.max(children.len());
+    stats.node_time += start.elapsed().saturating_sub(children_time);
+
+    tree.set_children(idx, &children);
+    tree.nodes[idx].potential_win = potential_win;
+    tree.nodes[idx].cutoff = cutoff;
 
-    SearchNode::new(Some(next_sq), Some(critical_sqs), potential_win, children)
+    idx
 }
 
-/// Thread safe version of tss_next_sq.
-pub fn tss_next_sq_safe(board: &Array2<u8>, color: u8, next_sq: Point, all_threats_init: &[Threat], opp_all_threats_init: &[Threat]) -> SearchNode {
-    let mut board_clone = board.to_owned();
-    tss_next_sq(&mut board_clone, color, next_sq, all_threats_init, opp_all_threats_init)
+/// Thread safe version of tss_next_sq. The search is done on a fork of tracker, and the subtree is returned as a separate tree.
+pub fn tss_next_sq_safe(tracker: &ThreatTracker, color: u8, next_sq: Point, depth: usize, ctx: &TSSContext, stats: &mut TSSStats) -> SearchTree {
+    let mut tracker_fork = tracker.fork();
+    let mut tree = SearchTree::default();
+    tss_next_sq(&mut tracker_fork, color, next_sq, depth, ctx, stats, &mut tree);
+    tree
 }
 
-/// Threat Space Search for the whole board.
-pub fn tss_board(board: &mut Array2<u8>, color: u8) -> SearchNode {
-    let threats = search_all_board(board, color, ThreatPri::Immediate);
-    let opp_threats = search_all_board(board, color ^ STONE, ThreatPri::Immediate);
+/// Threat Space Search for each of the given next_sqs, at the given depth.
+///
+/// Subtrees at depths up to limits.par_max_depth are searched in parallel, each on its own fork of the tracker (and with its own stats),
+/// if there are at least TSS_PAR_MIN_CHILDREN of them and more than one thread to run them on.
+/// Otherwise, they are searched serially, since forking the tracker costs more than it saves.
+/// The subtrees are added to tree, and the indices of their roots are returned.
+pub fn tss_children(
+    tracker: &mut ThreatTracker,
+    color: u8,
+    nsqs: &FnvHashSet<Point>,
+    depth: usize,
+    ctx: &TSSContext,
+    stats: &mut TSSStats,
+    tree: &mut SearchTree,
+) -> Vec<usize> {
+    if depth <= ctx.limits.par_max_depth && nsqs.len() >= TSS_PAR_MIN_CHILDREN && rayon::current_num_threads() > 1 {
+        let tracker: &ThreatTracker = tracker;
+        let (subtrees, children_stats): (Vec<SearchTree>, Vec<TSSStats>) = nsqs
+            .par_iter()
+            .map(|x| {
+                let mut child_stats = TSSStats::default();
+                let child = tss_next_sq_safe(tracker, color, *x, depth, ctx, &mut child_stats);
+                (child, child_stats)
+            })
+            .unzip();
+
+        for x in children_stats.iter() {
+            stats.merge(x);
+        }
 
-    let min_defcon = threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
-    let opp_min_defcon = opp_threats.iter().fold(MAX_DEFCON, |a, b| a.min(b.defcon));
+        subtrees.into_iter().map(|x| tree.append(x)).collect()
+    } else {
+        nsqs.iter().map(|x| tss_next_sq(tracker, color, *x, depth, ctx, stats, tree)).collect()
+    }
+}
 
-    let mut potential_win = !threats.is_empty() && min_defcon <= opp_min_defcon;
-    let mut children = Vec::<SearchNode>::new();
+/// Threat Space Search (VCT) for the whole board.
+pub fn tss_board(board: &mut Array2<u8>, color: u8) -> SearchTree {
+    tss_board_limited(board, color, TSSMode::VCT, &TSSLimits::default()).tree
+}
 
-    if !potential_win {
-        let nsqs = search_all_board_get_next_sqs(board, color, ThreatPri::Immediate);
-        children = nsqs
-            .par_iter()
-            .map(|x| tss_next_sq_safe(board, color, *x, &threats, &opp_threats))
-            .collect();
-        // children = nsqs.iter().map(|x| tss_next_sq(board, color, *x, &threats, &opp_threats)).collect();
-        potential_win = children.iter().any(|x| x.potential_win);
+/// Threat Space Search for the whole board, in the given mode and within the given limits.
+pub fn tss_board_limited(board: &mut Array2<u8>, color: u8, mode: TSSMode, limits: &TSSLimits) -> TSSResult {
+    let ctx = TSSContext::new(mode, limits);
+    let mut stats = TSSStats::default();
+    let start = Instant::now();
+    let mut children_time = Duration::ZERO;
+    stats.visit(0);
+
+    let search_start = Instant::now();
+    let mut tracker = ThreatTracker::new(board);
+    stats.search_time += search_start.elapsed();
+    stats.count_threats(tracker.threats(color).chain(tracker.threats(color ^ STONE)).map(|x| x.pidx));
+
+    let min_defcon = tracker.min_defcon(color);
+    let opp_min_defcon = tracker.min_defcon(color ^ STONE);
+
+    let mut potential_win = tracker.num_threats(color) > 0 && min_defcon <= opp_min_defcon;
+    let mut tree = SearchTree::default();
+    let root = tree.push(None, &FnvHashSet::<Point>::default(), potential_win, None);
+    let mut children = Vec::<usize>::new();
+    let mut cutoff: Option<TSSCutoff> = None;
+
+    if !potential_win && ctx.depth_reached(0) {
+        cutoff = Some(TSSCutoff::MaxDepth);
+    } else if !potential_win {
+        let nsqs = stats.search_next_sqs(|| search_all_board_get_next_sqs(board, color, ctx.mode.next_sq_pris()[0]));
+        let children_start = Instant::now();
+        children = tss_children(&mut tracker, color, &nsqs, 1, &ctx, &mut stats, &mut tree);
+        children_time += children_start.elapsed();
+        potential_win = children.iter().any(|&x| tree.node(x).potential_win);
     }
 
-    SearchNode::new(None, None, potential_win, children)
+    stats.max_branching_factor = stats.max_branching_factor.max(children.len());
+    stats.node_time += start.elapsed().saturating_sub(children_time);
+
+    tree.set_children(root, &children);
+    tree.nodes[root].potential_win = potential_win;
+    tree.nodes[root].cutoff = cutoff;
+
+    TSSResult {
+        tree,
+        finished: !ctx.cut_off.load(Ordering::Relaxed),
+        num_nodes: ctx.num_nodes.load(Ordering::Relaxed),
+        stats,
+    }
 }
 
-/// Extract all potentially winning variations from SearchNode.
-pub fn potential_win_variations(node: &SearchNode) -> Vec<Vec<(Point, FnvHashSet<Point>)>> {
+/// Extract all potentially winning variations from SearchTree, shortest first.
+pub fn potential_win_variations(tree: &SearchTree) -> Vec<Vec<(Point, FnvHashSet<Point>)>> {
+    let mut variations = node_potential_win_variations(tree, SearchTree::ROOT);
+    variations.sort_by_key(|x| x.len());
+    variations
+}
+
+/// Potentially winning variations starting at the given node.
+fn node_potential_win_variations(tree: &SearchTree, idx: usize) -> Vec<Vec<(Point, FnvHashSet<Point>)>> {
     let mut variations: Vec<Vec<(Point, FnvHashSet<Point>)>> = Vec::new();
+    let node = tree.node(idx);
 
     if node.potential_win {
         let mut node_var: Vec<(Point, FnvHashSet<Point>)> = Vec::new();
-        if node.next_sq.is_some() {
-            node_var.push((node.next_sq.unwrap(), node.critical_sqs.to_owned().unwrap()));
+        if let Some(next_sq) = node.next_sq {
+            node_var.push((next_sq, tree.critical_sqs(idx).iter().copied().collect()));
         }
 
-        if !node.children.is_empty() {
-            for child in node.children.iter() {
-                if child.potential_win {
-                    let child_variations = potential_win_variations(child);
-                    for child_var in child_variations {
-                        let mut child_var_next = node_var.to_owned();
-                        child_var_next.extend(child_var);
-                        variations.push(child_var_next);
-                    }
+        let mut children = tree.children(idx).peekable();
+        if children.peek().is_some() {
+            for child in children.filter(|&x| tree.node(x).potential_win) {
+                for child_var in node_potential_win_variations(tree, child) {
+                    let mut child_var_next = node_var.to_owned();
+                    child_var_next.extend(child_var);
+                    variations.push(child_var_next);
                 }
             }
         } else {
@@ -228,10 +613,93 @@ pub fn potential_win_variations(node: &SearchNode) -> Vec<Vec<(Point, FnvHashSet
         }
     }
 
-    variations.sort_by(|a, b| a.len().cmp(&b.len()));
     variations
 }
 
+/// Reduce potentially winning variations to a small set of essentially different ones.
+///
+/// Variations are considered shortest first, and one is dropped if its next_sqs include all the next_sqs of a variation already kept.
+/// This drops variations of the form A + B, where B is itself a potentially winning variation,
+/// as well as transpositions, i.e., the same next_sqs played in a different order.
+pub fn reduce_variations(variations: &[Vec<(Point, FnvHashSet<Point>)>]) -> Vec<Vec<(Point, FnvHashSet<Point>)>> {
+    let mut sorted = variations.iter().collect::<Vec<&Vec<(Point, FnvHashSet<Point>)>>>();
+    sorted.sort_by_key(|x| x.len());
+
+    let mut kept: Vec<Vec<(Point, FnvHashSet<Point>)>> = Vec::new();
+    let mut kept_sqs: Vec<FnvHashSet<Point>> = Vec::new();
+
+    for v in sorted {
+        let sqs = v.iter().map(|x| x.0).collect::<FnvHashSet<Point>>();
+
+        if !kept_sqs.iter().any(|x| x.is_subset(&sqs)) {
+            kept.push(v.to_owned());
+            kept_sqs.push(sqs);
+        }
+    }
+
+    kept
+}
+
+/// Result of an iterative deepening Threat Space Search.
+/// finished is true if a potential win was found, or if the whole tree was searched without finding one.
+#[derive(Clone, Debug)]
+pub struct TSSIterativeResult {
+    pub variations: Vec<Vec<(Point, FnvHashSet<Point>)>>,
+    pub depth: usize,
+    pub finished: bool,
+    pub num_nodes: usize,
+}
+
+/// Iterative deepening Threat Space Search for the whole board.
+///
+/// Searches with increasing max_depth, and stops at the first depth at which a potential win is found.
+/// Returns the shortest potentially winning variation, or all of them if all_shortest is true.
+/// limits.max_depth caps the depth of the search, and limits.max_nodes applies to all the iterations together.
+pub fn tss_board_iterative(board: &mut Array2<u8>, color: u8, mode: TSSMode, limits: &TSSLimits, all_shortest: bool) -> TSSIterativeResult {
+    let mut num_nodes: usize = 0;
+    let mut depth: usize = 0;
+
+    loop {
+        let iter_limits = TSSLimits::new(
+            Some(depth),
+            limits.max_nodes.map(|x| x.saturating_sub(num_nodes)),
+            limits.deadline,
+            Arc::clone(&limits.cancel),
+        );
+        let result = tss_board_limited(board, color, mode, &iter_limits);
+        num_nodes += result.num_nodes;
+
+        if result.tree.root().potential_win {
+            let mut variations = potential_win_variations(&result.tree);
+            let min_len = variations[0].len();
+            variations.retain(|x| x.len() == min_len);
+            variations.truncate(if all_shortest { variations.len() } else { 1 });
+
+            return TSSIterativeResult {
+                variations,
+                depth,
+                finished: true,
+                num_nodes,
+            };
+        }
+
+        // Stop if the whole tree was searched, or if we were cut off by anything other than max_depth.
+        let limits_reached = limits.cancel.load(Ordering::Relaxed)
+            || limits.deadline.is_some_and(|x| Instant::now() >= x)
+            || iter_limits.max_nodes.is_some_and(|x| result.num_nodes > x);
+        if result.finished || limits_reached || limits.max_depth.is_some_and(|x| depth >= x) {
+            return TSSIterativeResult {
+                variations: Vec::new(),
+                depth,
+                finished: result.finished,
+                num_nodes,
+            };
+        }
+
+        depth += 1;
+    }
+}
+
 /// Animate a given variation on the board.
 pub fn animate_variation(board: &mut Array2<u8>, color: u8, variation: &[(Point, FnvHashSet<Point>)]) {
     let sleep_duration = Duration::from_secs(ANIMATION_TIMESTEP_SECS);
@@ -0,0 +1,215 @@
+//! Incrementally maintained threats of both colors, as moves are made and taken back.
+
+use crate::bitboard::BitBoard;
+use crate::board::{clear_sq, set_sq};
+use crate::consts::{BLACK, MAX_DEFCON, WHITE};
+use crate::fixed_board::FixedBoard;
+use crate::geometry::{increments, point_is_on_line, slope_intercept, Point};
+use crate::pattern::{Threat, ThreatPri};
+use crate::pattern_search::Match;
+use crate::simd_search::search_all_board_simd;
+use fnv::FnvHashMap;
+use ndarray::prelude::*;
+
+/// A line on the board, in the modified slope intercept form. See slope_intercept.
+pub type Line = (isize, isize, isize);
+
+/// Index of the given color in per color arrays.
+#[inline(always)]
+fn color_idx(color: u8) -> usize {
+    match color {
+        BLACK => 0,
+        WHITE => 1,
+        _ => panic!("Invalid color!"),
+    }
+}
+
+/// Check if two matches lie on the same segment, in either orientation.
+#[inline(always)]
+fn same_segment(a: Match, b: Match) -> bool {
+    a == b || (a.0 == b.1 && a.1 == b.0)
+}
+
+/// The 4 lines passing through the given point.
+fn lines_through(point: Point) -> [Line; 4] {
+    let mut lines = [(0, 0, 0); 4];
+    for (d, line) in lines.iter_mut().enumerate() {
+        let (row_inc, col_inc) = increments(d);
+        *line = slope_intercept(point, (point.0 + row_inc, point.1 + col_inc));
+    }
+    lines
+}
+
+/// Changes made by a move, so that it can be taken back.
+#[derive(Clone, Debug)]
+struct Change {
+    color: u8,
+    point: Point,
+    removed: Vec<(usize, Line, Threat)>,
+    added: Vec<(usize, Line, Match, usize)>,
+}
+
+/// Board along with the (Immediate) threats of both colors, indexed by the line they lie on.
+///
+/// The board is kept both as an array and as a bitboard, which is used to search for the threats.
+/// Threats are deduplicated: a pattern appears at most once on a given segment.
+/// apply places a stone, and updates the threats passing through it, and revert takes back the latest move.
+#[derive(Clone, Debug)]
+pub struct ThreatTracker {
+    board: Array2<u8>,
+    bitboard: BitBoard,
+    threats: [FnvHashMap<Line, Vec<Threat>>; 2],
+    defcon_counts: [[usize; MAX_DEFCON + 1]; 2],
+    history: Vec<Change>,
+}
+
+impl ThreatTracker {
+    pub fn new(board: &Array2<u8>) -> Self {
+        let mut tracker = Self {
+            board: board.to_owned(),
+            bitboard: BitBoard::from_array(board),
+            threats: [FnvHashMap::default(), FnvHashMap::default()],
+            defcon_counts: [[0; MAX_DEFCON + 1]; 2],
+            history: Vec::new(),
+        };
+
+        let fixed_board = FixedBoard::from_array(board);
+        for color in [BLACK, WHITE] {
+            for t in search_all_board_simd(&fixed_board, color, ThreatPri::Immediate) {
+                tracker.insert(color_idx(color), slope_intercept(t.m.0, t.m.1), t);
+            }
+        }
+
+        tracker
+    }
+
+    /// Copy of the current board and threats, without the history of moves.
+    pub fn fork(&self) -> Self {
+        Self {
+            board: self.board.to_owned(),
+            bitboard: self.bitboard.clone(),
+            threats: self.threats.clone(),
+            defcon_counts: self.defcon_counts,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn board(&self) -> &Array2<u8> {
+        &self.board
+    }
+
+    pub fn bitboard(&self) -> &BitBoard {
+        &self.bitboard
+    }
+
+    /// Number of moves that can be taken back.
+    pub fn num_moves(&self) -> usize {
+        self.history.len()
+    }
+
+    /// All the threats of the given color.
+    pub fn threats(&self, color: u8) -> impl Iterator<Item = &Threat> {
+        self.threats[color_idx(color)].values().flatten()
+    }
+
+    /// Number of threats of the given color.
+    pub fn num_threats(&self, color: u8) -> usize {
+        self.defcon_counts[color_idx(color)].iter().sum()
+    }
+
+    /// Smallest defcon of the threats of the given color, MAX_DEFCON if there are none.
+    pub fn min_defcon(&self, color: u8) -> usize {
+        let counts = &self.defcon_counts[color_idx(color)];
+        (0..MAX_DEFCON).find(|&x| counts[x] > 0).unwrap_or(MAX_DEFCON)
+    }
+
+    /// Pattern indices of the threats found by the latest move.
+    pub fn last_added_pidxs(&self) -> impl Iterator<Item = usize> + '_ {
+        self.history.last().into_iter().flat_map(|x| x.added.iter().map(|y| y.3))
+    }
+
+    /// Insert a threat, unless it's already present. Returns true if it was inserted.
+    fn insert(&mut self, cidx: usize, line: Line, threat: Threat) -> bool {
+        let line_threats = self.threats[cidx].entry(line).or_default();
+
+        if line_threats.iter().any(|x| x.pidx == threat.pidx && same_segment(x.m, threat.m)) {
+            false
+        } else {
+            self.defcon_counts[cidx][threat.defcon] += 1;
+            line_threats.push(threat);
+            true
+        }
+    }
+
+    /// Place a stone of the given color at point, and update the threats of both colors.
+    pub fn apply(&mut self, color: u8, point: Point) {
+        set_sq(&mut self.board, color, point);
+        self.bitboard.set_sq(color, point);
+
+        let mut change = Change {
+            color,
+            point,
+            removed: Vec::new(),
+            added: Vec::new(),
+        };
+
+        // 1. Remove threats including point.
+        for line in lines_through(point) {
+            for cidx in 0..2 {
+                if let Some(line_threats) = self.threats[cidx].get_mut(&line) {
+                    let mut i = 0;
+                    while i < line_threats.len() {
+                        if point_is_on_line(point, line_threats[i].m.0, line_threats[i].m.1, true) {
+                            let t = line_threats.swap_remove(i);
+                            self.defcon_counts[cidx][t.defcon] -= 1;
+                            change.removed.push((cidx, line, t));
+                        } else {
+                            i += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        // 2. Add threats including point.
+        for c in [BLACK, WHITE] {
+            let cidx = color_idx(c);
+            for t in self.bitboard.search_all_point(c, point, ThreatPri::Immediate) {
+                let line = slope_intercept(t.m.0, t.m.1);
+                let (m, pidx) = (t.m, t.pidx);
+                if self.insert(cidx, line, t) {
+                    change.added.push((cidx, line, m, pidx));
+                }
+            }
+        }
+
+        self.history.push(change);
+    }
+
+    /// Take back the latest move.
+    pub fn revert(&mut self) {
+        let change = self.history.pop().expect("No move to revert!");
+
+        for (cidx, line, m, pidx) in change.added {
+            let line_threats = self.threats[cidx].get_mut(&line).unwrap();
+            let i = line_threats.iter().position(|x| x.pidx == pidx && x.m == m).unwrap();
+            let t = line_threats.swap_remove(i);
+            self.defcon_counts[cidx][t.defcon] -= 1;
+        }
+
+        for (cidx, line, t) in change.removed {
+            self.defcon_counts[cidx][t.defcon] += 1;
+            self.threats[cidx].entry(line).or_default().push(t);
+        }
+
+        clear_sq(&mut self.board, change.color, change.point);
+        self.bitboard.clear_sq(change.color, change.point);
+    }
+
+    /// Take back moves until only num_moves are left.
+    pub fn revert_to(&mut self, num_moves: usize) {
+        while self.history.len() > num_moves {
+            self.revert();
+        }
+    }
+}
@@ -2,15 +2,11 @@
 
 // *** TODO SOON ***
 // TODO: Add Victoria's games as test positions.
-// TODO: Remove or de-prioritize potential win variations of the form A + B, where B is another existing potential win variation.
 // TODO: Too many garbage variations when using LowPri threats!
 //       0) Check that the comprehensive threat handling is efficient.
-//       1) Dedupe all_threats!
-//       2) Make a note in search node when we cut off variation due to the opponent's potential win!
-//       3) Handling of win pattern consistently in threat space search.
-//       4) Add "TSS" to the names of types in threat space search.
+//       1) Handling of win pattern consistently in threat space search.
+//       2) Add "TSS" to the names of types in threat space search.
 // TODO: Add function to flip colors in a position and check that the code is agnostic to color by testing positions where win is known?
-// TODO: Parallelize TSS further? Right now it's only parallelized at the first level (depth = 1).
 // TODO: Fix critical_sqs pattern in tss_next_sq.
 // TODO: Refactor potential loss code.
 // TODO: Implement a transposition table. How do we cope with having different last_sqs?
@@ -22,9 +18,6 @@
 //       Use it to guide the development effort and debugging.
 // TODO: Check all test positions in paper. Implement regtests based on them.
 // TODO: Remove unnecessary fields from threat data.
-// TODO: Need function to search using a given sets of points as own sqs!
-//       Something efficient and proper, not some hack.
-// TODO: Need fn to search along direction at point?
 // TODO: Can we have a better way to input position?
 
 // *** Miscellaneous ***
@@ -34,11 +27,7 @@
 // TODO: Implement function to calculate intersection of pattern matches. What for though?
 
 // *** Position Evaluation (Static/Semi-Static) ***
-// TODO: Implement an evaluation scale.
-// TODO: Use all patterns to assign a value to the position (including P_2_D?).
 // TODO: null-move heuristic to see any short-term attacks?
-// TODO: Use Positional Evaluation for quiet/positions not determined by TSS.
-// TODO: Negamax Search?
 
 // *** Profiling and Testing ***
 // TODO: Create tests for algebraic related fns, state construction etc.